@@ -0,0 +1,27 @@
+//! Helpers shared by the command line tools.
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Returns the bytes of the given path exactly as it is named on disk, even if it is not valid
+/// UTF-8.
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+/// Returns the bytes of the given path, replacing anything which is not valid Unicode.
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+        Cow::Owned(path) => Cow::Owned(path.into_bytes())
+    }
+}
+
+/// Write the given path exactly as it is named on disk, or as near as the platform allows.
+pub fn write_path(out: &mut impl Write, path: &Path) -> io::Result<()> {
+    out.write_all(&path_bytes(path))
+}
@@ -1,17 +1,553 @@
 extern crate gitignore;
 
+mod common;
+
+use std::borrow::Cow;
 use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "usage: gitignore_check [<options>] <pathname>...
+   or: gitignore_check [<options>] --stdin
 
-/// Given a list of files, check the status of these files and whether they are excluded because
-/// of the .gitignore rules in the current working directory.
+    -q, --quiet           suppress progress reporting
+    -v, --verbose         be verbose
+    --stdin               read file names from stdin
+    -z                    terminate input and output records by a NUL character
+    -n, --non-matching    show non-matching input paths
+    --no-index            ignore index when checking
+";
+
+/// Checks whether each of the given paths is excluded by the ignore rules of the Git repository
+/// containing the current working directory, accepting the same options as `git check-ignore`
+/// and exiting with the same status: 0 if any path is ignored, 1 if none are, 128 on error and
+/// 129 if the command line is not understood.
+///
+/// The index is never read, so tracked files are checked like any other, just as they are by
+/// `git check-ignore --no-index`.
 pub fn main() {
-    let pwd = env::current_dir().unwrap();
-    let gitignore_path = pwd.join(".gitignore");
-    let file = gitignore::File::new(&gitignore_path).unwrap();
-
-    for arg in env::args().skip(1) {
-        let path = pwd.join(&arg);
-        let matches = file.is_excluded(&path).unwrap();
-        println!("File: {}, Excluded: {}", arg, matches);
+    let (stdin, stdout, stderr) = (io::stdin(), io::stdout(), io::stderr());
+    let mut err = stderr.lock();
+
+    let status = match env::current_dir() {
+        Ok(pwd) => execute(env::args_os().skip(1), &pwd, &mut stdin.lock(), &mut stdout.lock(), &mut err),
+        Err(cause) => report(Failure::from(cause), &mut err)
+    };
+    process::exit(status);
+}
+
+/// Run the command with the given arguments from the directory `pwd`, returning the status to
+/// exit with.
+fn execute<I>(args: I, pwd: &Path, input: &mut impl BufRead, out: &mut impl Write,
+              err: &mut impl Write) -> i32 where I: IntoIterator<Item = OsString> {
+    match Options::parse(args).and_then(|options| run(&options, pwd, input, out)) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(failure) => report(failure, err)
+    }
+}
+
+/// The options accepted on the command line, as for `git check-ignore`.
+#[derive(Debug, Default)]
+struct Options {
+    quiet: bool,
+    verbose: bool,
+    stdin: bool,
+    nul: bool,
+    non_matching: bool,
+    paths: Vec<OsString>
+}
+
+/// Why the command could not carry on.
+#[derive(Debug)]
+enum Failure {
+    /// The command line was not understood, so the usage should be shown.
+    Usage(String),
+    /// Anything else which went wrong.
+    Fatal(String)
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Failure::Usage(ref message) | Failure::Fatal(ref message) => f.write_str(message)
+        }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Failure {
+        Failure::Fatal(err.to_string())
+    }
+}
+
+impl From<gitignore::Error> for Failure {
+    fn from(err: gitignore::Error) -> Failure {
+        Failure::Fatal(err.to_string())
+    }
+}
+
+/// Report the given failure, returning the status Git exits with for it: 129 if the command line
+/// was not understood, and 128 for anything else.
+fn report(failure: Failure, err: &mut impl Write) -> i32 {
+    // There is nowhere left to report a failure to write the report.
+    match failure {
+        Failure::Usage(message) => {
+            let _ = writeln!(err, "error: {}\n{}", message, USAGE);
+            129
+        },
+        Failure::Fatal(message) => {
+            let _ = writeln!(err, "fatal: {}", message);
+            128
+        }
+    }
+}
+
+impl Options {
+    /// Parse the given arguments, checking that they make sense together.
+    fn parse<I>(args: I) -> Result<Options, Failure> where I: IntoIterator<Item = OsString> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--") => {
+                    options.paths.extend(args);
+                    break;
+                },
+                Some("--quiet") => options.quiet = true,
+                Some("--verbose") => options.verbose = true,
+                Some("--stdin") => options.stdin = true,
+                Some("--non-matching") => options.non_matching = true,
+                // The index is never consulted, so this is always the case.
+                Some("--no-index") => {},
+                Some(long) if long.starts_with("--") => {
+                    return Err(Failure::Usage(format!("unknown option `{}'", &long[2..])));
+                },
+                Some(short) if short.starts_with('-') && short.len() > 1 => {
+                    for flag in short[1..].chars() {
+                        match flag {
+                            'q' => options.quiet = true,
+                            'v' => options.verbose = true,
+                            'z' => options.nul = true,
+                            'n' => options.non_matching = true,
+                            _ => return Err(Failure::Usage(format!("unknown switch `{}'", flag)))
+                        }
+                    }
+                },
+                _ => options.paths.push(arg)
+            }
+        }
+
+        let invalid = if options.stdin && !options.paths.is_empty() {
+            Some("cannot specify pathnames with --stdin")
+        } else if !options.stdin && options.paths.is_empty() {
+            Some("no path specified")
+        } else if options.quiet && options.paths.len() > 1 {
+            Some("--quiet is only valid with a single pathname")
+        } else if options.quiet && options.verbose {
+            Some("cannot have both --quiet and --verbose")
+        } else if options.non_matching && !options.verbose {
+            Some("--non-matching is only valid with --verbose")
+        } else if options.nul && !options.stdin {
+            Some("-z only makes sense with --stdin")
+        } else {
+            None
+        };
+
+        match invalid {
+            Some(message) => Err(Failure::Fatal(message.to_string())),
+            None => Ok(options)
+        }
+    }
+}
+
+/// Check every path given, either on the command line or on standard input, returning whether
+/// any of them were ignored.
+fn run(options: &Options, pwd: &Path, input: &mut impl BufRead,
+       out: &mut impl Write) -> Result<bool, Failure> {
+    let root = find_root(pwd).ok_or_else(|| {
+        Failure::Fatal("not a git repository (or any of the parent directories): .git".to_string())
+    })?;
+    let repository = gitignore::Repository::open(&root)?;
+
+    let mut ignored = false;
+
+    if options.stdin {
+        let delimiter = if options.nul { b'\0' } else { b'\n' };
+        let mut line = vec![];
+
+        while input.read_until(delimiter, &mut line)? > 0 {
+            // As Git's `strbuf_getline` does, a line ending in CRLF loses both, unless the records
+            // are terminated by NUL.
+            if line.last() == Some(&delimiter) {
+                line.pop();
+                if !options.nul && line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+            }
+            // As in Git, a line starting with a double quote is a C-style quoted path.
+            let path = if !options.nul && line.first() == Some(&b'"') {
+                let unquoted = unquote_c_style(&line);
+                Cow::Owned(unquoted.ok_or_else(|| Failure::Fatal("line is badly quoted".to_string()))?)
+            } else {
+                Cow::Borrowed(line.as_slice())
+            };
+            ignored |= check(&repository, &root, pwd, &os_string(&path), options, out)?;
+            // Whoever is writing the paths may be waiting to read the answer before sending more.
+            out.flush()?;
+            line.clear();
+        }
+    } else {
+        for path in &options.paths {
+            ignored |= check(&repository, &root, pwd, path, options, out)?;
+        }
+    }
+
+    out.flush()?;
+    Ok(ignored)
+}
+
+/// Check a single path, writing out the result as requested, and returning whether it counts as
+/// ignored. As in Git, a path which matches a negated pattern counts as ignored if the pattern is
+/// being shown, and the paths written are quoted unless records are terminated by NUL.
+fn check(repository: &gitignore::Repository, root: &Path, pwd: &Path, path: &OsString, options: &Options,
+         out: &mut impl Write) -> Result<bool, Failure> {
+    let abs_path = normalize(&pwd.join(path));
+    if !abs_path.starts_with(root) {
+        let (path, root) = (Path::new(path).display(), root.display());
+        return Err(Failure::Fatal(format!("{}: '{}' is outside repository at '{}'", path, path, root)));
+    }
+
+    let relative = abs_path.strip_prefix(root).unwrap_or(&abs_path);
+    if is_beyond_symlink(root, relative) {
+        let message = format!("pathspec '{}' is beyond a symbolic link", relative.display());
+        return Err(Failure::Fatal(message));
+    }
+
+    let is_dir = fs::symlink_metadata(&abs_path).map(|metadata| metadata.is_dir()).unwrap_or(false);
+    let found = repository.explain(&abs_path, is_dir).filter(|found| options.verbose || found.is_excluded());
+
+    if options.quiet {
+        return Ok(found.is_some());
+    }
+
+    let end: &[u8] = if options.nul { b"\0" } else { b"\n" };
+    let separator: &[u8] = if options.nul { b"\0" } else { b":" };
+    let tab: &[u8] = if options.nul { b"\0" } else { b"\t" };
+
+    match found {
+        Some(ref found) if options.verbose => {
+            let source = found.source().map(|source| source.strip_prefix(root).unwrap_or(source));
+            write_name(out, source.unwrap_or_else(|| Path::new("")), options.nul)?;
+            out.write_all(separator)?;
+            write!(out, "{}", found.line())?;
+            out.write_all(separator)?;
+            out.write_all(found.trimmed_text())?;
+            out.write_all(tab)?;
+        },
+        Some(_) => {},
+        None if options.non_matching => {
+            out.write_all(separator)?;
+            out.write_all(separator)?;
+            out.write_all(tab)?;
+        },
+        None => return Ok(false)
+    }
+
+    write_name(out, Path::new(path), options.nul)?;
+    out.write_all(end)?;
+    Ok(found.is_some())
+}
+
+/// Write the given path as Git writes names: exactly as it is if records are terminated by NUL,
+/// and quoted if need be otherwise.
+fn write_name(out: &mut impl Write, path: &Path, nul: bool) -> io::Result<()> {
+    if nul {
+        common::write_path(out, path)
+    } else {
+        out.write_all(&quote_c_style(&common::path_bytes(path)))
+    }
+}
+
+/// Quote the given name as Git's `quote_c_style` does if it contains a control character, a double
+/// quote, a backslash or any byte which is not ASCII: the whole name is wrapped in double quotes,
+/// with each such byte escaped as in C or written as three octal digits. Any other name is left
+/// as it is.
+fn quote_c_style(name: &[u8]) -> Cow<'_, [u8]> {
+    let unusual = |byte: u8| !(b' '..0x7f).contains(&byte) || byte == b'"' || byte == b'\\';
+    if !name.iter().any(|&byte| unusual(byte)) {
+        return Cow::Borrowed(name);
+    }
+
+    let mut quoted = vec![b'"'];
+    for &byte in name {
+        match byte {
+            0x07 => quoted.extend_from_slice(b"\\a"),
+            0x08 => quoted.extend_from_slice(b"\\b"),
+            b'\t' => quoted.extend_from_slice(b"\\t"),
+            b'\n' => quoted.extend_from_slice(b"\\n"),
+            0x0b => quoted.extend_from_slice(b"\\v"),
+            0x0c => quoted.extend_from_slice(b"\\f"),
+            b'\r' => quoted.extend_from_slice(b"\\r"),
+            b'"' | b'\\' => quoted.extend_from_slice(&[b'\\', byte]),
+            _ if unusual(byte) => quoted.extend_from_slice(format!("\\{:03o}", byte).as_bytes()),
+            _ => quoted.push(byte)
+        }
+    }
+    quoted.push(b'"');
+    Cow::Owned(quoted)
+}
+
+/// Undo `quote_c_style`, as Git's `unquote_c_style` does, returning `None` if the given text is
+/// not a validly quoted name. Anything after the closing quote is ignored.
+fn unquote_c_style(quoted: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = quoted.strip_prefix(b"\"")?.iter().cloned();
+    let mut name = vec![];
+
+    loop {
+        match bytes.next()? {
+            b'"' => return Some(name),
+            b'\\' => {},
+            byte => {
+                name.push(byte);
+                continue;
+            }
+        }
+
+        let byte = match bytes.next()? {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'v' => 0x0b,
+            b'f' => 0x0c,
+            b'r' => b'\r',
+            byte @ (b'"' | b'\\') => byte,
+            // Only three octal digits starting with 0 to 3 fit in a byte.
+            first @ b'0'..=b'3' => {
+                let mut value = first - b'0';
+                for _ in 0..2 {
+                    match bytes.next()? {
+                        digit @ b'0'..=b'7' => value = value << 3 | (digit - b'0'),
+                        _ => return None
+                    }
+                }
+                value
+            },
+            _ => return None
+        };
+        name.push(byte);
+    }
+}
+
+/// Returns true if any directory leading to the given path, relative to the root, is really a
+/// symbolic link. Git refuses to check such a path, as it cannot be part of the worktree.
+fn is_beyond_symlink(root: &Path, relative: &Path) -> bool {
+    relative.ancestors().skip(1).filter(|dir| !dir.as_os_str().is_empty()).any(|dir| {
+        fs::symlink_metadata(root.join(dir)).is_ok_and(|metadata| metadata.file_type().is_symlink())
+    })
+}
+
+/// Find the top of the worktree containing the given directory: the nearest directory holding a
+/// `.git` directory, or a `.git` file as used by linked worktrees.
+fn find_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find(|dir| dir.join(".git").exists()).map(Path::to_path_buf)
+}
+
+/// Resolve any `.` and `..` components of the given absolute path without touching the
+/// filesystem, as Git does with the paths it is given.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            _ => normalized.push(component)
+        }
+    }
+    normalized
+}
+
+/// Turn a path read from standard input back into the name it refers to.
+#[cfg(unix)]
+fn os_string(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes.to_vec())
+}
+
+/// Turn a path read from standard input back into the name it refers to, replacing anything which
+/// is not valid UTF-8.
+#[cfg(not(unix))]
+fn os_string(bytes: &[u8]) -> OsString {
+    OsString::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::{execute, quote_c_style, unquote_c_style, Failure, Options};
+
+    use std::ffi::OsString;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_options() {
+        let options = Options::parse(args(&["-vn", "--no-index", "--", "-q", "a"])).unwrap();
+        assert!(options.verbose && options.non_matching && !options.quiet);
+        assert!(options.paths == args(&["-q", "a"]));
+
+        let options = Options::parse(args(&["--stdin", "-z", "--quiet"])).unwrap();
+        assert!(options.stdin && options.nul && options.quiet && options.paths.is_empty());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_options() {
+        let usage = |arguments: &[&str]| match Options::parse(args(arguments)) {
+            Err(Failure::Usage(message)) => message,
+            other => panic!("unexpected {:?}", other)
+        };
+
+        assert!(usage(&["--bogus", "a"]) == "unknown option `bogus'");
+        assert!(usage(&["-vx", "a"]) == "unknown switch `x'");
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_combinations() {
+        let fatal = |arguments: &[&str]| match Options::parse(args(arguments)) {
+            Err(Failure::Fatal(message)) => message,
+            other => panic!("unexpected {:?}", other)
+        };
+
+        assert!(fatal(&[]) == "no path specified");
+        assert!(fatal(&["--stdin", "a"]) == "cannot specify pathnames with --stdin");
+        assert!(fatal(&["-q", "a", "b"]) == "--quiet is only valid with a single pathname");
+        assert!(fatal(&["-qv", "a"]) == "cannot have both --quiet and --verbose");
+        assert!(fatal(&["-n", "a"]) == "--non-matching is only valid with --verbose");
+        assert!(fatal(&["-z", "a"]) == "-z only makes sense with --stdin");
+    }
+
+    #[test]
+    fn test_quote_c_style() {
+        assert!(&*quote_c_style(b"plain name.log") == b"plain name.log");
+        assert!(&*quote_c_style(b"caf\xc3\xa9.log") == b"\"caf\\303\\251.log\"");
+        assert!(&*quote_c_style(b"tab\tx.log") == b"\"tab\\tx.log\"");
+        assert!(&*quote_c_style(b"q\"uote.log") == b"\"q\\\"uote.log\"");
+        assert!(&*quote_c_style(b"back\\slash\x01\x7f") == b"\"back\\\\slash\\001\\177\"");
+    }
+
+    #[test]
+    fn test_unquote_c_style() {
+        assert!(unquote_c_style(b"\"caf\\303\\251.log\"").unwrap() == b"caf\xc3\xa9.log");
+        assert!(unquote_c_style(b"\"tab\\tx.log\"").unwrap() == b"tab\tx.log");
+        assert!(unquote_c_style(b"\"q\\\"uote.log\" ignored").unwrap() == b"q\"uote.log");
+        assert!(unquote_c_style(b"\"\\a\\b\\f\\n\\r\\v\\\\\"").unwrap() == b"\x07\x08\x0c\n\r\x0b\\");
+
+        assert!(unquote_c_style(b"plain").is_none());
+        assert!(unquote_c_style(b"\"unterminated").is_none());
+        assert!(unquote_c_style(b"\"\\x\"").is_none());
+        assert!(unquote_c_style(b"\"\\400\"").is_none());
+        assert!(unquote_c_style(b"\"\\38\"").is_none());
+
+        for name in [&b"caf\xc3\xa9\t\"\\\x01\x7f"[..], b"plain"] {
+            assert!(unquote_c_style(&quote_c_style(name)).unwrap_or_else(|| name.to_vec()) == name);
+        }
+    }
+
+    #[test]
+    fn test_exit_status() {
+        with_fake_repo(|root| {
+            assert!(run(root, &["a.log"], b"") == (0, b"a.log\n".to_vec(), vec![]));
+            assert!(run(root, &["--no-index", "a.txt"], b"") == (1, vec![], vec![]));
+
+            let (status, _, err) = run(root, &["--bogus"], b"");
+            assert!(status == 129 && err.starts_with(b"error: unknown option `bogus'\nusage: "));
+
+            let (status, _, err) = run(root, &["-q", "a.log", "b.log"], b"");
+            assert!(status == 128 && err == b"fatal: --quiet is only valid with a single pathname\n");
+
+            let (status, _, err) = run(&root.join("sub"), &["../../x.log"], b"");
+            assert!(status == 128);
+            assert!(err.starts_with(b"fatal: ../../x.log: '../../x.log' is outside repository"));
+        })
+    }
+
+    #[test]
+    fn test_quoted_paths() {
+        with_fake_repo(|root| {
+            let expected = b"\"caf\\303\\251.log\"\n\"tab\\tx.log\"\n";
+            let input = b"\"caf\\303\\251.log\"\ntab\tx.log\na.txt\n";
+            assert!(run(root, &["--stdin"], input) == (0, expected.to_vec(), vec![]));
+
+            let input = b"caf\xc3\xa9.log\0\"quoted\".log\0";
+            let expected = b"caf\xc3\xa9.log\0\"quoted\".log\0";
+            assert!(run(root, &["--stdin", "-z"], input) == (0, expected.to_vec(), vec![]));
+
+            let input = b"a.log\r\n\"b.log\"\r\nc.log\r";
+            assert!(run(root, &["--stdin"], input) == (0, b"a.log\nb.log\n".to_vec(), vec![]));
+            assert!(run(root, &["--stdin", "-z"], b"a.log\r\0") == (1, vec![], vec![]));
+
+            let (status, _, err) = run(root, &["--stdin"], b"\"unterminated\n");
+            assert!(status == 128 && err == b"fatal: line is badly quoted\n");
+        })
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_paths_beyond_symlinks_are_refused() {
+        use std::os::unix::fs::symlink;
+
+        with_fake_repo(|root| {
+            symlink(root.join("sub"), root.join("link")).unwrap();
+            fs::write(root.join("sub/inner.log"), "").unwrap();
+
+            assert!(run(root, &["link"], b"") == (1, vec![], vec![]));
+            assert!(run(root, &["sub/inner.log"], b"").0 == 0);
+
+            let expected = b"fatal: pathspec 'link/inner.log' is beyond a symbolic link\n";
+            assert!(run(root, &["-v", "link/inner.log"], b"") == (128, vec![], expected.to_vec()));
+            assert!(run(&root.join("sub"), &["--stdin"], b"../link/inner.log\n").2 == expected);
+        })
+    }
+
+    #[test]
+    fn test_verbose_shows_trimmed_pattern() {
+        with_fake_repo(|root| {
+            let expected = b".gitignore:2:foo\tfoo\n.gitignore:3:!keep.log\tkeep.log\n::\tbar\n";
+            assert!(run(root, &["-vn", "foo", "keep.log", "bar"], b"") == (0, expected.to_vec(), vec![]));
+
+            let expected = b".gitignore\x002\0foo\0foo\0";
+            assert!(run(root, &["-vz", "--stdin"], b"foo\0") == (0, expected.to_vec(), vec![]));
+        })
+    }
+
+    fn args(arguments: &[&str]) -> Vec<OsString> {
+        arguments.iter().map(OsString::from).collect()
+    }
+
+    /// Run the command from `pwd`, returning its exit status and everything written to standard
+    /// output and standard error.
+    fn run(pwd: &Path, arguments: &[&str], input: &[u8]) -> (i32, Vec<u8>, Vec<u8>) {
+        let (mut out, mut err) = (vec![], vec![]);
+        let status = execute(args(arguments), pwd, &mut &input[..], &mut out, &mut err);
+        (status, out, err)
+    }
+
+    fn with_fake_repo<F>(callback: F) where F: Fn(&Path) {
+        let dir = tempdir::TempDir::new("gitignore_check_tests").unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::create_dir_all(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join(".gitignore"), "*.log\nfoo   \n!keep.log\n").unwrap();
+
+        callback(dir.path());
+        dir.close().unwrap();
     }
 }
@@ -1,16 +1,398 @@
 extern crate gitignore;
 
+mod common;
+
 use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "usage: gitignore_tree [<options>]
+
+    -r, --relative        print paths relative to the current directory
+    -0, --null            terminate each path with a NUL character instead of a newline
+    --json                print a JSON array of objects with the path, type and size of each entry
+    --ndjson              print one JSON object per line, as for --json
+    -f, --files-only      only list entries which are not directories
+    -d, --dirs-only       only list directories
+    --max-depth <n>       do not list anything more than <n> directories deep
+    --ignored             list what is excluded instead of what is included
+    -h, --help            show this help
+
+Single letter options may be combined, eg. -rf is the same as -r -f.
+";
 
 /// Traverses the directory trees from the current working directory downwards, listing all the
 /// files that are _not_ excluded because of the .gitignore rules. The rules are also loaded from
-/// the current working directory.
+/// the current working directory. Options allow the listing to be filtered, inverted, or written
+/// in a format other scripts can read.
+///
+/// Exits with status 0 on success, 1 if any part of the tree could not be read, and 2 if the
+/// options were not understood. Output stopping early because whatever is reading it has gone
+/// away, as with `gitignore_tree | head`, counts as success.
 pub fn main() {
-    let pwd = env::current_dir().unwrap();
+    let options = match Options::parse(env::args_os().skip(1).map(|arg| arg.to_string_lossy().into_owned())) {
+        Ok(options) => options,
+        Err(message) => {
+            eprint!("error: {}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let result = if options.help {
+        out.write_all(USAGE.as_bytes()).map(|_| true).map_err(gitignore::Error::from)
+    } else {
+        env::current_dir().map_err(gitignore::Error::from)
+            .and_then(|pwd| run(&options, &pwd, &mut out, &mut io::stderr()))
+    };
+
+    match result {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(ref err) if is_broken_pipe(err) => {},
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Returns true if the given error came from writing to a pipe which has been closed by whatever
+/// was reading from it.
+fn is_broken_pipe(err: &gitignore::Error) -> bool {
+    let cause = err.source().and_then(|source| source.downcast_ref::<io::Error>());
+    cause.is_some_and(|cause| cause.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// How each entry is written out.
+#[derive(Debug, PartialEq)]
+enum Format {
+    Plain,
+    Json,
+    Ndjson
+}
+
+/// The options accepted on the command line.
+#[derive(Debug, PartialEq)]
+struct Options {
+    relative: bool,
+    null: bool,
+    format: Format,
+    files: bool,
+    dirs: bool,
+    max_depth: Option<usize>,
+    ignored: bool,
+    help: bool
+}
+
+impl Options {
+    /// Parse the given arguments, checking that they make sense together. Single letter options
+    /// may be combined into one argument.
+    fn parse<I>(args: I) -> Result<Options, String> where I: IntoIterator<Item = String> {
+        let mut options = Options {
+            relative: false,
+            null: false,
+            format: Format::Plain,
+            files: true,
+            dirs: true,
+            max_depth: None,
+            ignored: false,
+            help: false
+        };
+        let mut args = args.into_iter().flat_map(split_short_flags);
+
+        while let Some(arg) = args.next() {
+            let (flag, value) = match arg.find('=') {
+                Some(equals) if arg.starts_with("--") => (&arg[..equals], Some(&arg[equals + 1..])),
+                _ => (&arg[..], None)
+            };
+
+            match flag {
+                "-r" | "--relative" => options.relative = true,
+                "-0" | "--null" => options.null = true,
+                "--json" => options.format = Format::Json,
+                "--ndjson" => options.format = Format::Ndjson,
+                "-f" | "--files-only" => options.dirs = false,
+                "-d" | "--dirs-only" => options.files = false,
+                "--ignored" => options.ignored = true,
+                "-h" | "--help" => options.help = true,
+                "--max-depth" => {
+                    let value = match value {
+                        Some(value) => value.to_string(),
+                        None => args.next().ok_or("--max-depth requires a value")?
+                    };
+                    let depth = value.parse().map_err(|_| format!("invalid depth '{}'", value))?;
+                    options.max_depth = Some(depth);
+                },
+                _ => return Err(format!("unknown option '{}'", arg))
+            }
+        }
+
+        if !options.files && !options.dirs {
+            return Err("--files-only and --dirs-only cannot be used together".to_string());
+        }
+        if options.null && options.format != Format::Plain {
+            return Err("--null cannot be used with --json or --ndjson".to_string());
+        }
+
+        Ok(options)
+    }
+}
+
+/// Split an argument holding several single letter options, such as `-rf`, into one argument
+/// for each of them. Any other argument is left as it is.
+fn split_short_flags(arg: String) -> Vec<String> {
+    if arg.starts_with('-') && !arg.starts_with("--") && arg.chars().count() > 2 {
+        arg.chars().skip(1).map(|flag| format!("-{}", flag)).collect()
+    } else {
+        vec![arg]
+    }
+}
+
+/// List the tree under `pwd` as requested, reporting anything which could not be read to `err`, and
+/// returning false if there was any.
+fn run(options: &Options, pwd: &Path, out: &mut impl Write,
+       err: &mut impl Write) -> Result<bool, gitignore::Error> {
     let gitignore_path = pwd.join(".gitignore");
-    let file = gitignore::File::new(&gitignore_path).unwrap();
+    let file = gitignore::File::new(&gitignore_path)?;
+
+    let walk = file.walk_builder()
+        .files(options.files)
+        .dirs(options.dirs)
+        .max_depth(options.max_depth)
+        .ignored(options.ignored)
+        .build();
+
+    let mut complete = true;
+    let mut first = true;
+
+    if options.format == Format::Json {
+        out.write_all(b"[")?;
+    }
+
+    for entry in walk {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(cause) => {
+                writeln!(err, "error: {}", cause)?;
+                complete = false;
+                continue;
+            }
+        };
+
+        let path = if options.relative {
+            entry.path().strip_prefix(pwd).unwrap_or_else(|_| entry.path())
+        } else {
+            entry.path()
+        };
+
+        match options.format {
+            Format::Plain => {
+                common::write_path(out, path)?;
+                out.write_all(if options.null { b"\0" } else { b"\n" })?;
+            },
+            Format::Json => {
+                out.write_all(if first { b"\n  " } else { b",\n  " })?;
+                write_json(out, &entry, path)?;
+            },
+            Format::Ndjson => {
+                write_json(out, &entry, path)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        first = false;
+    }
+
+    if options.format == Format::Json {
+        out.write_all(if first { b"]\n" } else { b"\n]\n" })?;
+    }
+
+    out.flush()?;
+    Ok(complete)
+}
+
+/// Write the given entry as a JSON object, eg. `{"path":"src/lib.rs","type":"file","size":42}`.
+/// The size of a directory is `null`. Paths which are not valid Unicode cannot be written in JSON,
+/// so anything invalid in them is replaced.
+fn write_json(out: &mut impl Write, entry: &gitignore::DirEntry, path: &Path) -> io::Result<()> {
+    let file_type = entry.file_type();
+    let kind = if file_type.is_dir() {
+        "dir"
+    } else if file_type.is_file() {
+        "file"
+    } else if file_type.is_symlink() {
+        "symlink"
+    } else {
+        "other"
+    };
+
+    out.write_all(b"{\"path\":")?;
+    write_json_string(out, &path.to_string_lossy())?;
+    write!(out, ",\"type\":\"{}\",\"size\":", kind)?;
+    match fs::metadata(entry.path()) {
+        Ok(ref metadata) if !metadata.is_dir() => write!(out, "{}", metadata.len())?,
+        _ => out.write_all(b"null")?
+    }
+    out.write_all(b"}")
+}
+
+/// Write the given text as a JSON string, escaping it as required.
+fn write_json_string(out: &mut impl Write, text: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?
+        }
+    }
+    out.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::{is_broken_pipe, run, write_json_string, Format, Options};
+
+    use std::fs;
+    use std::io::{self, Write};
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_options() {
+        let options = Options::parse(args(&[])).unwrap();
+        assert!(!options.relative && !options.null && options.format == Format::Plain);
+        assert!(options.files && options.dirs && options.max_depth.is_none() && !options.ignored);
+
+        let options = Options::parse(args(&["--relative", "--json", "--max-depth=2", "--ignored"])).unwrap();
+        assert!(options.relative && options.format == Format::Json);
+        assert!(options.max_depth == Some(2) && options.ignored);
+
+        let options = Options::parse(args(&["--max-depth", "3", "--files-only"])).unwrap();
+        assert!(options.max_depth == Some(3) && options.files && !options.dirs);
+    }
+
+    #[test]
+    fn test_parse_combined_short_flags() {
+        assert!(Options::parse(args(&["-rf"])) == Options::parse(args(&["-r", "-f"])));
+        assert!(Options::parse(args(&["-0d"])) == Options::parse(args(&["-0", "--dirs-only"])));
+
+        let options = Options::parse(args(&["-r0h"])).unwrap();
+        assert!(options.relative && options.null && options.help);
+        assert!(Options::parse(args(&["-rx"])).unwrap_err() == "unknown option '-x'");
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_options() {
+        let error = |arguments: &[&str]| Options::parse(args(arguments)).unwrap_err();
+
+        assert!(error(&["--bogus"]) == "unknown option '--bogus'");
+        assert!(error(&["-fd"]) == "--files-only and --dirs-only cannot be used together");
+        assert!(error(&["-0", "--ndjson"]) == "--null cannot be used with --json or --ndjson");
+        assert!(error(&["--max-depth"]) == "--max-depth requires a value");
+        assert!(error(&["--max-depth=deep"]) == "invalid depth 'deep'");
+    }
+
+    #[test]
+    fn test_max_depth() {
+        with_fake_tree(|root| {
+            assert!(list(root, &["-r", "--max-depth", "1"]) == vec![".gitignore", "a.txt", "sub"]);
+            assert!(list(root, &["-rf", "--max-depth=2"]) == vec![".gitignore", "a.txt", "sub/b.txt"]);
+            assert!(list(root, &["-rd"]) == vec!["sub", "sub/deeper"]);
+        })
+    }
+
+    #[test]
+    fn test_ignored() {
+        with_fake_tree(|root| {
+            assert!(list(root, &["-r", "--ignored"]) == vec!["a.log", "sub/b.log", "target"]);
+            assert!(list(root, &["-rf", "--ignored"]) == vec!["a.log", "sub/b.log"]);
+        })
+    }
+
+    #[test]
+    fn test_json() {
+        with_fake_tree(|root| {
+            let options = Options::parse(args(&["-r", "--ndjson", "--max-depth=1", "-f"])).unwrap();
+            let mut out = vec![];
+            assert!(run(&options, root, &mut out, &mut io::sink()).unwrap());
+
+            let mut lines: Vec<_> = String::from_utf8(out).unwrap().lines().map(String::from).collect();
+            lines.sort();
+            assert!(lines == vec![
+                "{\"path\":\".gitignore\",\"type\":\"file\",\"size\":14}",
+                "{\"path\":\"a.txt\",\"type\":\"file\",\"size\":0}"
+            ]);
+        })
+    }
+
+    #[test]
+    fn test_write_json_string() {
+        let mut out = vec![];
+        write_json_string(&mut out, "caf\u{e9} \"q\" back\\slash\n\r\t\u{1}\u{7f}").unwrap();
+        assert!(out == "\"caf\u{e9} \\\"q\\\" back\\\\slash\\n\\r\\t\\u0001\u{7f}\"".as_bytes());
+    }
+
+    #[test]
+    fn test_broken_pipe_is_not_an_error() {
+        struct ClosedPipe;
+
+        impl Write for ClosedPipe {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "Broken pipe"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        with_fake_tree(|root| {
+            let options = Options::parse(args(&[])).unwrap();
+            let err = run(&options, root, &mut ClosedPipe, &mut io::sink()).unwrap_err();
+            assert!(is_broken_pipe(&err));
+        });
+
+        let err = gitignore::Error::from(io::Error::new(io::ErrorKind::PermissionDenied, "denied"));
+        assert!(!is_broken_pipe(&err));
+    }
+
+    fn args(arguments: &[&str]) -> Vec<String> {
+        arguments.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// List the tree under `root` with the given options, one path per line, in sorted order.
+    fn list(root: &Path, arguments: &[&str]) -> Vec<String> {
+        let options = Options::parse(args(arguments)).unwrap();
+        let mut out = vec![];
+        assert!(run(&options, root, &mut out, &mut io::sink()).unwrap());
+
+        let mut paths: Vec<_> = String::from_utf8(out).unwrap().lines().map(String::from).collect();
+        paths.sort();
+        paths
+    }
+
+    fn with_fake_tree<F>(callback: F) where F: Fn(&Path) {
+        let dir = tempdir::TempDir::new("gitignore_tree_tests").unwrap();
+        let files = ["a.txt", "a.log", "sub/b.txt", "sub/b.log", "sub/deeper/c.txt", "target/d.txt"];
+        for file in files.iter() {
+            let path = dir.path().join(file);
+            path.parent().map(fs::create_dir_all);
+            fs::write(path, "").unwrap();
+        }
+        fs::write(dir.path().join(".gitignore"), "*.log\ntarget/\n").unwrap();
 
-    for path in file.included_files().unwrap() {
-        println!("{}", path.to_str().unwrap());
+        callback(dir.path());
+        dir.close().unwrap();
     }
 }
@@ -1,45 +1,130 @@
-extern crate glob;
-
 use std::error;
 use std::fmt;
 
 use std::io;
+use std::path::{Path, PathBuf};
+
+use file;
+use wildmatch;
 
-/// Encapsulates all the types of errors this crate can produce.
+/// Encapsulates all the types of errors this crate can produce. Use `kind` to find out what went
+/// wrong, and `path` to find out which file or directory it concerned.
 pub struct Error {
-    repr: ErrorCause
+    repr: ErrorCause,
+    path: Option<PathBuf>
+}
+
+/// The kinds of error this crate can produce, as returned by `Error::kind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A file or directory could not be read. The underlying I/O error is available from
+    /// `Error::source`.
+    Io,
+    /// A pattern can never match anything, such as when a character class is never closed. The
+    /// `line` is 1-based, or 0 if the pattern was not read from a file, and the `column` is the
+    /// 1-based position, counted in characters, at which the problem was found.
+    InvalidPattern { line: usize, column: usize },
+    /// A path which must be absolute, such as the root of a repository, was relative.
+    NotAbsolute,
+    /// Text which must be valid UTF-8 was not, such as the path of the user's excludes file read
+    /// from the Git configuration on a platform where paths are not bytes. The file it was read
+    /// from is given by `Error::path`.
+    InvalidUtf8,
+    /// A symbolic link found while walking a directory tree leads back to one of its own
+    /// ancestors, so it was not followed. Its path is given by `Error::path`.
+    FilesystemLoop
 }
 
 #[derive(Debug)]
 enum ErrorCause {
     IoError(io::Error),
-    PatternError(glob::PatternError)
+    PatternError(wildmatch::PatternError),
+    InvalidPattern(file::Diagnostic),
+    NotAbsolute,
+    // Paths are bytes on Unix, so only text read on other platforms needs to be valid UTF-8.
+    #[cfg_attr(unix, allow(dead_code))]
+    InvalidUtf8,
+    FilesystemLoop
+}
+
+impl Error {
+    /// Returns what kind of error this is.
+    pub fn kind(&self) -> ErrorKind {
+        match self.repr {
+            ErrorCause::IoError(_) => ErrorKind::Io,
+            ErrorCause::PatternError(ref err) => ErrorKind::InvalidPattern { line: 0, column: err.pos + 1 },
+            ErrorCause::InvalidPattern(ref diagnostic) => {
+                ErrorKind::InvalidPattern { line: diagnostic.line, column: diagnostic.column }
+            },
+            ErrorCause::NotAbsolute => ErrorKind::NotAbsolute,
+            ErrorCause::InvalidUtf8 => ErrorKind::InvalidUtf8,
+            ErrorCause::FilesystemLoop => ErrorKind::FilesystemLoop
+        }
+    }
+
+    /// Returns the path of the file or directory the error concerns, if there is one.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the location of the invalid line and a description of the problem, if this error
+    /// was caused by a `.gitignore` file containing an invalid pattern.
+    pub fn diagnostic(&self) -> Option<&file::Diagnostic> {
+        match self.repr {
+            ErrorCause::InvalidPattern(ref diagnostic) => Some(diagnostic),
+            _ => None
+        }
+    }
+
+    /// An I/O error which occurred while reading the given file or directory.
+    pub(crate) fn io(error: io::Error, path: &Path) -> Error {
+        Error { repr: ErrorCause::IoError(error), path: Some(path.to_path_buf()) }
+    }
+
+    /// The given path was relative where an absolute path is required.
+    pub(crate) fn not_absolute(path: &Path) -> Error {
+        Error { repr: ErrorCause::NotAbsolute, path: Some(path.to_path_buf()) }
+    }
+
+    /// Text read from the file at the given path was not valid UTF-8.
+    #[cfg_attr(unix, allow(dead_code))]
+    pub(crate) fn invalid_utf8(path: &Path) -> Error {
+        Error { repr: ErrorCause::InvalidUtf8, path: Some(path.to_path_buf()) }
+    }
+
+    /// The symbolic link at the given path leads back to one of its own ancestors.
+    pub(crate) fn filesystem_loop(path: &Path) -> Error {
+        Error { repr: ErrorCause::FilesystemLoop, path: Some(path.to_path_buf()) }
+    }
 }
 
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Error {
-        Error { repr: ErrorCause::IoError(error) }
+        Error { repr: ErrorCause::IoError(error), path: None }
     }
 }
 
-impl From<glob::PatternError> for Error {
-    fn from(error: glob::PatternError) -> Error {
-        Error { repr: ErrorCause::PatternError(error) }
+impl From<wildmatch::PatternError> for Error {
+    fn from(error: wildmatch::PatternError) -> Error {
+        Error { repr: ErrorCause::PatternError(error), path: None }
     }
 }
 
-impl error::Error for Error {
-    fn description(&self) -> &str {
-        match self.repr {
-            ErrorCause::IoError(ref err) => err.description(),
-            ErrorCause::PatternError(ref err) => err.msg
-        }
+impl From<file::Diagnostic> for Error {
+    fn from(diagnostic: file::Diagnostic) -> Error {
+        let path = diagnostic.source.as_ref().map(|source| source.to_path_buf());
+        Error { repr: ErrorCause::InvalidPattern(diagnostic), path }
     }
+}
 
-    fn cause(&self) -> Option<&dyn error::Error> {
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self.repr {
-            ErrorCause::IoError(ref err) => Some(err as &dyn error::Error),
-            _ => None
+            ErrorCause::IoError(ref err) => Some(err),
+            ErrorCause::PatternError(ref err) => Some(err),
+            ErrorCause::InvalidPattern(_) | ErrorCause::NotAbsolute | ErrorCause::InvalidUtf8 |
+            ErrorCause::FilesystemLoop => None
         }
     }
 }
@@ -48,10 +133,28 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self.repr {
             ErrorCause::IoError(ref err) => {
-                err.fmt(f)
+                match self.path {
+                    Some(ref path) => write!(f, "{}: {}", path.display(), err),
+                    None => err.fmt(f)
+                }
             },
             ErrorCause::PatternError(ref err) => {
                 err.fmt(f)
+            },
+            ErrorCause::InvalidPattern(ref diagnostic) => {
+                diagnostic.fmt(f)
+            },
+            ErrorCause::NotAbsolute => {
+                let path = self.path().unwrap_or_else(|| Path::new(""));
+                write!(f, "{}: path is not absolute", path.display())
+            },
+            ErrorCause::InvalidUtf8 => {
+                let path = self.path().unwrap_or_else(|| Path::new(""));
+                write!(f, "{}: contains text which is not valid UTF-8", path.display())
+            },
+            ErrorCause::FilesystemLoop => {
+                let path = self.path().unwrap_or_else(|| Path::new(""));
+                write!(f, "{}: symbolic link leads back to an ancestor directory", path.display())
             }
         }
     }
@@ -62,3 +165,41 @@ impl fmt::Debug for Error {
         fmt::Display::fmt(self, f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, ErrorKind};
+
+    use std::error::Error as StdError;
+    use std::io;
+    use std::path::Path;
+
+    #[test]
+    fn test_io_error_has_path_and_source() {
+        let cause = io::Error::new(io::ErrorKind::NotFound, "not found");
+        let err = Error::io(cause, Path::new("/wing/.gitignore"));
+
+        assert!(err.kind() == ErrorKind::Io);
+        assert!(err.path() == Some(Path::new("/wing/.gitignore")));
+        assert!(err.to_string() == "/wing/.gitignore: not found");
+        assert!(err.source().unwrap().to_string() == "not found");
+    }
+
+    #[test]
+    fn test_not_absolute() {
+        let err = Error::not_absolute(Path::new("wing"));
+
+        assert!(err.kind() == ErrorKind::NotAbsolute);
+        assert!(err.to_string() == "wing: path is not absolute");
+        assert!(err.source().is_none());
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let err = Error::invalid_utf8(Path::new("/wing/.git/config"));
+
+        assert!(err.kind() == ErrorKind::InvalidUtf8);
+        assert!(err.to_string() == "/wing/.git/config: contains text which is not valid UTF-8");
+        assert!(err.source().is_none());
+    }
+}
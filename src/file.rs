@@ -1,9 +1,13 @@
 use error;
 use pattern;
+use set;
+use walk;
 
+use std::fmt;
 use std::fs;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Represents a `.gitignore` file. Use this to load the `.gitignore` file, parse the patterns,
 /// and then check if a given path would be excluded by any rules contained therein.
@@ -19,105 +23,372 @@ use std::path::{Path, PathBuf};
 /// assert!(file.is_excluded(&path_to_test_if_excluded).unwrap())
 /// ```
 #[derive(Debug)]
-pub struct File<'a> {
-    patterns: Vec<pattern::Pattern<'a>>,
-    root: &'a Path
+pub struct File {
+    patterns: set::PatternSet,
+    diagnostics: Vec<Diagnostic>,
+    root: Arc<Path>
 }
 
-impl<'b> File<'b> {
+/// A problem found with a line of a `.gitignore` file, such as a pattern which can never match
+/// anything. Git silently ignores such lines, and so does `File` unless it was created with
+/// `File::new_strict`, but they are kept so they can be reported.
+///
+/// When displayed, this is formatted as the source file, line and column separated by colons,
+/// followed by the message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The file the line was read from, or `None` if it was parsed from a string or reader.
+    pub source: Option<Arc<Path>>,
+    /// The 1-based line number of the problem.
+    pub line: usize,
+    /// The 1-based column, counted in characters, at which the problem was found.
+    pub column: usize,
+    /// The line exactly as it was written.
+    pub text: Vec<u8>,
+    /// A message describing the problem.
+    pub message: String
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if let Some(ref source) = self.source {
+            write!(f, "{}:", source.display())?;
+        }
+
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl File {
     /// Parse the given `.gitignore` file for patterns, allowing any arbitrary path to be checked
     /// against the set of rules to test for exclusion.
     ///
+    /// Lines which are not valid patterns are skipped, just as Git would, but are reported by
+    /// `diagnostics`.
+    ///
+    /// The value of `gitignore_path` must be an absolute path.
+    pub fn new(gitignore_path: &Path) -> Result<File, error::Error> {
+        let root = match gitignore_path.parent() {
+            Some(root) if gitignore_path.is_absolute() => root,
+            _ => return Err(error::Error::not_absolute(gitignore_path))
+        };
+
+        File::load(gitignore_path, Arc::from(root))
+    }
+
+    /// Parse the given `.gitignore` file for patterns exactly as `new` does, but fail if any line
+    /// is not a valid pattern. The error describes the first such line, and `Error::diagnostic`
+    /// gives its location.
+    ///
     /// The value of `gitignore_path` must be an absolute path.
-    pub fn new(gitignore_path: &'b Path) -> Result<File<'b>, error::Error> {
-        let root = gitignore_path.parent().unwrap();
-        let patterns = File::patterns(gitignore_path, root)?;
+    pub fn new_strict(gitignore_path: &Path) -> Result<File, error::Error> {
+        let file = File::new(gitignore_path)?;
+
+        match file.diagnostics.first() {
+            Some(diagnostic) => Err(diagnostic.clone().into()),
+            None => Ok(file)
+        }
+    }
+
+    /// Parse the given string for patterns, exactly as if it were the contents of a `.gitignore`
+    /// file residing in the directory `root`. The filesystem is never touched.
+    ///
+    /// The value of `root` must be an absolute path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// let file = gitignore::File::from_str("*.o\n/build", Path::new("/src")).unwrap();
+    /// assert!(file.matches_path(Path::new("/src/lib/foo.o"), false));
+    /// assert!(!file.matches_path(Path::new("/src/lib/build"), true));
+    /// ```
+    pub fn from_str(contents: &str, root: &Path) -> Result<File, error::Error> {
+        File::from_bytes(contents.as_bytes(), root)
+    }
+
+    /// Parse the given bytes for patterns, exactly as `from_str` does, but without requiring them
+    /// to be valid UTF-8. Like Git, the contents of a `.gitignore` file are treated as bytes, so
+    /// patterns can match file names in any encoding.
+    ///
+    /// The value of `root` must be an absolute path.
+    pub fn from_bytes(contents: &[u8], root: &Path) -> Result<File, error::Error> {
+        if !root.is_absolute() {
+            return Err(error::Error::not_absolute(root));
+        }
+
+        Ok(File::parse(contents, Arc::from(root), None))
+    }
+
+    /// Read the given reader to the end and parse the contents for patterns, exactly as if they
+    /// were the contents of a `.gitignore` file residing in the directory `root`.
+    ///
+    /// The value of `root` must be an absolute path.
+    pub fn from_reader(reader: impl Read, root: &Path) -> Result<File, error::Error> {
+        if !root.is_absolute() {
+            return Err(error::Error::not_absolute(root));
+        }
+
+        File::read(reader, Arc::from(root), None)
+    }
+
+    /// Parse the given file for patterns, anchoring them to the given root rather than to the
+    /// directory the file happens to reside in.
+    pub(crate) fn load(path: &Path, root: Arc<Path>) -> Result<File, error::Error> {
+        let reader = fs::File::open(path).map_err(|err| error::Error::io(err, path))?;
+        File::read(reader, root, Some(Arc::from(path)))
+    }
+
+    /// Returns every pattern in the file, in the order they were written. Blank lines and comments
+    /// are not included, but each pattern records the line it was found on and its original
+    /// text.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// let file = gitignore::File::from_str("# objects\n*.o\n\n!keep.o", Path::new("/src")).unwrap();
+    /// let rules: Vec<(usize, String)> = file.patterns().iter()
+    ///     .map(|pattern| (pattern.line, pattern.to_string()))
+    ///     .collect();
+    /// assert!(rules == vec![(2, "*.o".to_string()), (4, "!keep.o".to_string())]);
+    /// ```
+    pub fn patterns(&self) -> &[pattern::Pattern] {
+        self.patterns.patterns()
+    }
+
+    /// Returns a diagnostic for every line of the file which was skipped because it is not a
+    /// valid pattern, in the order they were written.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// let file = gitignore::File::from_str("*.o\nfoo[", Path::new("/src")).unwrap();
+    /// assert!(file.patterns().len() == 1);
+    /// assert!(file.diagnostics()[0].to_string() == "2:4: unclosed character class");
+    /// ```
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
 
-        Ok(File { patterns, root })
+    /// Sets whether the patterns in the file are matched case sensitively, as with Git's
+    /// `core.ignoreCase` configuration variable. This defaults to `true`, except on macOS and
+    /// Windows where filesystems are usually case insensitive.
+    pub fn set_case_sensitive(&mut self, case_sensitive: bool) {
+        self.patterns.set_case_sensitive(case_sensitive);
     }
 
     /// Returns true if, after checking against all the patterns found in the `.gitignore` file,
     /// the given path is matched any of the globs (applying negated patterns as expected). Note
-    /// this function also returns false if the path does not exist.
+    /// this function also returns true if the path does not exist, or lies outside of the
+    /// directory containing the `.gitignore` file.
+    ///
+    /// A path is also excluded if any of its parent directories are excluded, exactly as
+    /// `included_files` would never descend into them. Only the path and its ancestors are
+    /// checked, so this does not walk the rest of the directory tree.
+    ///
+    /// If the value for `path` is not absolute, it will assumed to be relative to the directory
+    /// containing the `.gitignore` file.
+    pub fn is_excluded(&self, path: &Path) -> Result<bool, error::Error> {
+        let abs_path = self.abs_path(path);
+        walk::is_excluded(&self.root, &abs_path, |path, directory| self.matches_path(path, directory))
+    }
+
+    /// Returns true if the given path, or any of its parent directories, would be excluded by the
+    /// patterns found in the `.gitignore` file. The second argument, `is_dir`, is a `bool`
+    /// representing whether the given path should be treated as a directory; every parent of the
+    /// path is always treated as a directory.
     ///
-    /// If the value for `path` is not absolute, it will assumed to be relative to the current
-    /// working directory.
-    pub fn is_excluded(&self, path: &'b Path) -> Result<bool, error::Error> {
-        self.included_files().map(|files| !files.contains(&path.to_path_buf()))
+    /// Unlike `is_excluded`, this never touches the filesystem, so it can be used to check paths
+    /// that do not exist yet. Paths outside of the directory containing the `.gitignore` file are
+    /// never matched.
+    ///
+    /// If the value for `path` is not absolute, it will assumed to be relative to the directory
+    /// containing the `.gitignore` file.
+    pub fn matches_path(&self, path: &Path, is_dir: bool) -> bool {
+        let abs_path = self.abs_path(path);
+        walk::any_ancestor(&self.root, &abs_path, is_dir, |path, directory| {
+            self.file_is_excluded(path, directory)
+        })
+    }
+
+    /// Returns the pattern which decided whether the given path is excluded, along with where it
+    /// was written, or `None` if no pattern matched the path or any of its parent directories.
+    /// This is the information `git check-ignore -v` reports, and is useful for debugging why a
+    /// path is or is not excluded. The second argument, `is_dir`, is a `bool` representing
+    /// whether the given path should be treated as a directory.
+    ///
+    /// If a parent directory is excluded, the pattern which excluded it is returned. Otherwise, the
+    /// last pattern to match the path is returned, even if it is negated; use
+    /// `Match::is_excluded` to tell the two apart. Like `matches_path`, this never touches the
+    /// filesystem.
+    ///
+    /// If the value for `path` is not absolute, it will assumed to be relative to the directory
+    /// containing the `.gitignore` file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::path::Path;
+    /// let file = gitignore::File::from_str("*.log\n!keep.log", Path::new("/src")).unwrap();
+    /// let found = file.explain(Path::new("keep.log"), false).unwrap();
+    /// assert!(found.line() == 2 && found.text() == b"!keep.log" && !found.is_excluded());
+    /// assert!(file.explain(Path::new("lib.rs"), false).is_none());
+    /// ```
+    pub fn explain(&self, path: &Path, is_dir: bool) -> Option<pattern::Match<'_>> {
+        let abs_path = self.abs_path(path);
+        walk::explain(&self.root, &abs_path, is_dir, |path, directory| self.last_match(path, directory))
     }
 
     /// Returns a list of files that are not excluded by the rules in the loaded
     /// `.gitignore` file. It recurses through all subdirectories and returns
     /// everything that is not ignored.
+    ///
+    /// This stops at the first error; use `walk` to carry on past errors, or to process a large
+    /// tree without collecting every path first.
     pub fn included_files(&self) -> Result<Vec<PathBuf>, error::Error> {
-        let mut files: Vec<PathBuf> = vec![];
-        let mut roots = vec![self.root.to_path_buf()];
-
-        while let Some(root) = roots.pop() {
-            let entries = fs::read_dir(root)?;
-
-            for entry in entries {
-                let path = entry?.path();
-                if path.ends_with(".git") {
-                    continue;
-                }
-
-                let matches = self.file_is_excluded(&path);
-                if matches.is_err() || matches? {
-                    continue;
-                }
+        self.walk().map(|entry| entry.map(walk::DirEntry::into_path)).collect()
+    }
 
-                files.push(path.to_path_buf());
+    /// Returns an iterator which lazily walks the directory containing the `.gitignore` file and
+    /// all of its subdirectories, yielding everything that is not excluded. Excluded directories
+    /// are not descended into. An error reading any directory or entry is yielded in its place,
+    /// and the walk carries on with the rest of the tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::env;
+    /// # let pwd = env::current_dir().unwrap();
+    /// # let gitignore_path = pwd.join(".gitignore");
+    /// let file = gitignore::File::new(&gitignore_path).unwrap();
+    /// for entry in file.walk() {
+    ///     match entry {
+    ///         Ok(entry) => assert!(!entry.path().starts_with(pwd.join("target"))),
+    ///         Err(err) => eprintln!("{}", err)
+    ///     }
+    /// }
+    /// ```
+    pub fn walk(&self) -> walk::Walk<'_> {
+        self.walk_builder().build()
+    }
 
-                let metadata = fs::metadata(&path);
-                if metadata.is_ok() && metadata?.is_dir() {
-                    roots.push(path);
-                }
-            }
-        }
+    /// Returns a walk over the same tree as `walk` which reads directories on several threads at
+    /// once, for very large trees. Use `run` to start it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use std::env;
+    /// # let pwd = env::current_dir().unwrap();
+    /// # let gitignore_path = pwd.join(".gitignore");
+    /// let file = gitignore::File::new(&gitignore_path).unwrap();
+    /// let mut count = 0;
+    /// file.walk_parallel().threads(4).run(|entry| {
+    ///     if entry.is_ok() {
+    ///         count += 1;
+    ///     }
+    /// });
+    /// assert!(count > 0);
+    /// ```
+    pub fn walk_parallel(&self) -> walk::WalkParallel<'_> {
+        self.walk_builder().build_parallel()
+    }
 
-        Ok(files)
+    /// Returns a builder for a walk over the same tree as `walk`, which can limit its depth,
+    /// whether it follows symbolic links, and what it yields.
+    pub fn walk_builder(&self) -> walk::WalkBuilder<'_> {
+        walk::WalkBuilder::new(&self.root, move |path, directory| self.file_is_excluded(path, directory))
     }
 
     /// Returns true if, after checking against all the patterns found in the `.gitignore` file,
     /// the given path is matched any of the globs (applying negated patterns as expected).
     ///
-    /// If the value for `path` is not absolute, it will assumed to be relative to the current
-    /// working directory.
+    /// The value of `path` must be an absolute path.
     ///
     /// Note very importantly that this method _does not_ check if the parent directories are
     /// excluded. This is only for determining if the file itself matched any rules.
-    fn file_is_excluded(&self, path: &'b Path) -> Result<bool, error::Error> {
-        let abs_path = self.abs_path(path);
-        let directory = fs::metadata(&abs_path)?.is_dir();
-        Ok(self.patterns.iter().fold(false, |acc, pattern| {
-            let matches = pattern.is_excluded(&abs_path, directory);
-            if !matches {
-                acc
-            } else {
-                !pattern.negation
+    fn file_is_excluded(&self, path: &Path, directory: bool) -> bool {
+        self.apply_patterns(false, path, directory)
+    }
+
+    /// Apply the patterns in the file to the given path, starting from the existing exclusion
+    /// state `excluded`, which the last matching pattern overrides. This allows patterns from
+    /// several files to be layered on top of one another, with later files taking precedence.
+    pub(crate) fn apply_patterns(&self, excluded: bool, path: &Path, directory: bool) -> bool {
+        match self.patterns.last_match(path, directory) {
+            Some(index) => !self.patterns()[index].negation,
+            None => excluded
+        }
+    }
+
+    /// Returns the last pattern in the file to match the given absolute path, if any, without
+    /// checking the parent directories.
+    pub(crate) fn last_match(&self, path: &Path, directory: bool) -> Option<pattern::Match<'_>> {
+        self.patterns.last_match(path, directory)
+            .map(|index| pattern::Match { pattern: &self.patterns()[index], path: path.to_path_buf() })
+    }
+
+    /// Read the given reader to the end and parse the contents for patterns. Any error is
+    /// reported against `source`, if given.
+    fn read(mut reader: impl Read, root: Arc<Path>, source: Option<Arc<Path>>) -> Result<File, error::Error> {
+        let mut bytes = vec![];
+        if let Err(err) = reader.read_to_end(&mut bytes) {
+            return Err(match source {
+                Some(ref source) => error::Error::io(err, source),
+                None => err.into()
+            });
+        }
+
+        Ok(File::parse(&bytes, root, source))
+    }
+
+    /// Given the contents of a `.gitignore` file and the root folder within which it resides,
+    /// parse out all the patterns. Blank lines (once trailing spaces are stripped) and lines
+    /// starting with a `#` are skipped; a pattern beginning with a literal `#` must escape it with
+    /// a backslash instead.
+    ///
+    /// Each pattern records its line number and `source`, the file the contents were read from.
+    /// Lines which are not valid patterns are skipped, and a diagnostic recorded for each instead.
+    fn parse(contents: &[u8], root: Arc<Path>, source: Option<Arc<Path>>) -> File {
+        let mut patterns = vec![];
+        let mut diagnostics = vec![];
+
+        // As in Git, a UTF-8 byte order mark, as some Windows editors write, is not part of the
+        // first pattern.
+        let contents = contents.strip_prefix(b"\xef\xbb\xbf").unwrap_or(contents);
+
+        // Lines end with a newline, optionally preceded by a carriage return.
+        let lines = contents.split(|&b| b == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+
+        for (index, line) in lines.enumerate() {
+            if pattern::trim_trailing_spaces(line).is_empty() || line.starts_with(b"#") {
+                continue;
             }
-        }))
-    }
-
-    /// Given the path to the `.gitignore` file and the root folder within which it resides,
-    /// parse out all the patterns and collect them up into a vector of patterns.
-    fn patterns(path: &'b Path, root: &'b Path) -> Result<Vec<pattern::Pattern<'b>>, error::Error> {
-        let mut file = fs::File::open(path)?;
-        let mut s = String::new();
-        file.read_to_string(&mut s)?;
-        Ok(s.lines().filter_map(|line| {
-            if !line.trim().is_empty() {
-                pattern::Pattern::new(line, root).ok()
-            } else {
-                None
+
+            match pattern::Pattern::with_root(line, root.clone()) {
+                Ok(mut pattern) => {
+                    pattern.line = index + 1;
+                    pattern.source = source.clone();
+                    patterns.push(pattern);
+                },
+                Err(err) => diagnostics.push(Diagnostic {
+                    source: source.clone(),
+                    line: index + 1,
+                    column: pattern::char_offset(line, err.pos) + 1,
+                    text: line.to_vec(),
+                    message: err.msg.to_string()
+                })
             }
-        }).collect())
+        }
+
+        File { patterns: set::PatternSet::new(patterns), diagnostics, root }
     }
 
     /// Given a path, make it absolute if relative by joining it to a given root, otherwise leave
     /// absolute as originally given.
-    fn abs_path(&self, path: &'b Path) -> PathBuf {
+    fn abs_path(&self, path: &Path) -> PathBuf {
         if path.is_absolute() {
             path.to_owned()
         } else {
@@ -128,14 +399,19 @@ impl<'b> File<'b> {
 
 #[cfg(test)]
 mod tests {
-    extern crate glob;
     extern crate tempdir;
 
     use super::File;
 
+    use error::ErrorKind;
+
+    use testing::assert_owned;
+
     use std::fs;
     use std::io::Write;
     use std::path::{Path,PathBuf};
+    use std::sync::Arc;
+    use std::thread;
 
     #[cfg(feature = "nightly")]
     use test::Bencher;
@@ -175,6 +451,290 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_is_excluded_with_excluded_parent() {
+        with_fake_repo("woo", vec!["win", "woo/hoo", "woo/boo/shoo"], |test_env| {
+            let file = File::new(test_env.gitignore).unwrap();
+            let root = test_env.gitignore.parent().unwrap();
+
+            assert!(!file.is_excluded(&root.join("win")).unwrap());
+            assert!(file.is_excluded(&root.join("woo")).unwrap());
+            assert!(file.is_excluded(&root.join("woo/hoo")).unwrap());
+            assert!(file.is_excluded(&root.join("woo/boo/shoo")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_is_excluded_outside_root_or_missing() {
+        with_fake_repo("*.foo", vec!["bar"], |test_env| {
+            let file = File::new(test_env.gitignore).unwrap();
+            let root = test_env.gitignore.parent().unwrap();
+
+            assert!(!file.is_excluded(Path::new("bar")).unwrap());
+            assert!(file.is_excluded(&root.join("missing")).unwrap());
+            assert!(file.is_excluded(Path::new("/definitely/not/in/the/root")).unwrap());
+            assert!(file.is_excluded(&root.join(".git/config")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_matches_path_without_touching_disk() {
+        with_fake_repo("*.foo\nout/", vec![], |test_env| {
+            let file = File::new(test_env.gitignore).unwrap();
+            let root = test_env.gitignore.parent().unwrap();
+
+            assert!(file.matches_path(&root.join("bar.foo"), false));
+            assert!(file.matches_path(Path::new("nested/bar.foo"), false));
+            assert!(!file.matches_path(Path::new("bar"), false));
+            assert!(file.matches_path(Path::new("out"), true));
+            assert!(!file.matches_path(Path::new("out"), false));
+            assert!(file.matches_path(Path::new("out/bar"), false));
+            assert!(!file.matches_path(Path::new("/definitely/not/in/the/root/bar"), false));
+        })
+    }
+
+    #[test]
+    fn test_from_str() {
+        let file = File::from_str("*.foo\n\n/out", Path::new("/wing")).unwrap();
+
+        assert!(file.matches_path(Path::new("/wing/bar.foo"), false));
+        assert!(file.matches_path(Path::new("/wing/out"), false));
+        assert!(!file.matches_path(Path::new("/wing/lux/out"), false));
+        assert!(!file.matches_path(Path::new("/lux/bar.foo"), false));
+    }
+
+    #[test]
+    fn test_new_errors() {
+        let err = File::new(Path::new("wing/.gitignore")).unwrap_err();
+        assert!(err.kind() == ErrorKind::NotAbsolute);
+
+        with_fake_repo("", vec![], |test_env| {
+            let missing = test_env.gitignore.parent().unwrap().join("missing/.gitignore");
+            let err = File::new(&missing).unwrap_err();
+            assert!(err.kind() == ErrorKind::Io);
+            assert!(err.path() == Some(missing.as_path()));
+        })
+    }
+
+    #[test]
+    fn test_from_bytes_with_non_utf8_patterns() {
+        let file = File::from_bytes(b"caf\xe9/\r\n*.\xe9t\xe9\r\nfoo[\xe9\n", Path::new("/wing")).unwrap();
+
+        assert!(file.patterns().len() == 2);
+        assert!(file.patterns()[0].text == b"caf\xe9/");
+        assert!(file.patterns()[1].pattern == b"*.\xe9t\xe9");
+        assert!(file.diagnostics()[0].line == 3);
+        assert!(file.diagnostics()[0].column == 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_file_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        with_fake_repo("", vec![], |test_env| {
+            let root = test_env.gitignore.parent().unwrap();
+            fs::write(test_env.gitignore, b"*.\xe9t\xe9\n!r\xe9sum\xe9.\xe9t\xe9\n").unwrap();
+
+            let summer = root.join(OsStr::from_bytes(b"summer.\xe9t\xe9"));
+            let resume = root.join(OsStr::from_bytes(b"r\xe9sum\xe9.\xe9t\xe9"));
+            write_to_file(&summer, "");
+            write_to_file(&resume, "");
+
+            let file = File::new(test_env.gitignore).unwrap();
+            assert!(file.is_excluded(&summer).unwrap());
+            assert!(!file.is_excluded(&resume).unwrap());
+
+            let files = file.included_files().unwrap();
+            assert!(files.contains(&resume));
+            assert!(!files.contains(&summer));
+        })
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let file = File::from_reader("*.foo".as_bytes(), Path::new("/wing")).unwrap();
+
+        assert!(file.matches_path(Path::new("bar.foo"), false));
+        assert!(!file.matches_path(Path::new("bar"), false));
+    }
+
+    #[test]
+    fn test_constructors_reject_relative_roots() {
+        let root = Path::new("wing");
+
+        assert!(File::from_str("*.foo", root).unwrap_err().kind() == ErrorKind::NotAbsolute);
+        assert!(File::from_bytes(b"*.foo", root).unwrap_err().kind() == ErrorKind::NotAbsolute);
+        assert!(File::from_reader("*.foo".as_bytes(), root).unwrap_err().kind() == ErrorKind::NotAbsolute);
+    }
+
+    #[test]
+    fn test_file_is_owned_and_shareable_across_threads() {
+        with_fake_repo("*.foo", vec!["bar.foo"], |test_env| {
+            let file = Arc::new(File::new(test_env.gitignore).unwrap());
+            assert_owned(&file);
+
+            let path = test_env.paths[0].clone();
+            let shared = file.clone();
+            let excluded = thread::spawn(move || shared.is_excluded(&path).unwrap());
+            assert!(excluded.join().unwrap());
+        })
+    }
+
+    #[test]
+    fn test_comments_are_skipped() {
+        let file = File::from_str("# comment\n#foo\n\\#bar\n   \n  #baz", Path::new("/wing")).unwrap();
+
+        assert!(file.patterns().len() == 2);
+        assert!(!file.matches_path(Path::new("# comment"), false));
+        assert!(!file.matches_path(Path::new("#foo"), false));
+        assert!(file.matches_path(Path::new("#bar"), false));
+        assert!(file.matches_path(Path::new("  #baz"), false));
+    }
+
+    #[test]
+    fn test_trailing_whitespace() {
+        let file = File::from_str("foo   \nbar\\ \n\t", Path::new("/wing")).unwrap();
+
+        assert!(file.patterns().len() == 3);
+        assert!(file.matches_path(Path::new("foo"), false));
+        assert!(!file.matches_path(Path::new("foo   "), false));
+        assert!(file.matches_path(Path::new("bar "), false));
+        assert!(!file.matches_path(Path::new("bar"), false));
+    }
+
+    #[test]
+    fn test_byte_order_mark_is_skipped() {
+        let contents = b"\xef\xbb\xbf*.log\r\n\xef\xbb\xbf*.tmp\r\n";
+        let file = File::from_bytes(contents, Path::new("/wing")).unwrap();
+
+        assert!(file.patterns()[0].text == b"*.log");
+        assert!(file.matches_path(Path::new("a.log"), false));
+        // Only a mark at the very start of the file is skipped.
+        assert!(!file.matches_path(Path::new("a.tmp"), false));
+    }
+
+    #[test]
+    fn test_set_case_sensitive() {
+        let mut file = File::from_str("foo.o\n!Bar.o", Path::new("/wing")).unwrap();
+
+        file.set_case_sensitive(true);
+        assert!(file.matches_path(Path::new("foo.o"), false));
+        assert!(!file.matches_path(Path::new("Foo.o"), false));
+
+        file.set_case_sensitive(false);
+        assert!(file.matches_path(Path::new("Foo.o"), false));
+        assert!(file.matches_path(Path::new("FOO.O"), false));
+    }
+
+    #[test]
+    fn test_escaped_negation_is_literal() {
+        let file = File::from_str("*.txt\n\\!important!.txt\n!keep.txt", Path::new("/wing")).unwrap();
+
+        assert!(file.matches_path(Path::new("!important!.txt"), false));
+        assert!(file.matches_path(Path::new("foo.txt"), false));
+        assert!(!file.matches_path(Path::new("keep.txt"), false));
+    }
+
+    #[test]
+    fn test_invalid_patterns_are_reported() {
+        let file = File::from_str("*.o\nfoo[\n!bär\\\n*.a", Path::new("/wing")).unwrap();
+
+        assert!(file.patterns().len() == 2);
+        assert!(file.matches_path(Path::new("lib.a"), false));
+
+        let diagnostics = file.diagnostics();
+        assert!(diagnostics.len() == 2);
+        assert!(diagnostics[0].source.is_none());
+        assert!(diagnostics[0].line == 2);
+        assert!(diagnostics[0].column == 4);
+        assert!(diagnostics[0].text == b"foo[");
+        assert!(diagnostics[0].message == "unclosed character class");
+        assert!(diagnostics[1].to_string() == "3:5: trailing backslash escapes nothing");
+    }
+
+    #[test]
+    fn test_new_strict() {
+        with_fake_repo("*.o\n\n/out[\n", vec![], |test_env| {
+            assert!(File::new(test_env.gitignore).unwrap().diagnostics().len() == 1);
+
+            let err = File::new_strict(test_env.gitignore).unwrap_err();
+            assert!(err.kind() == ErrorKind::InvalidPattern { line: 3, column: 5 });
+            assert!(err.path() == Some(test_env.gitignore));
+            let diagnostic = err.diagnostic().unwrap();
+            assert!(diagnostic.source.as_deref() == Some(test_env.gitignore));
+            assert!(diagnostic.line == 3);
+            assert!(diagnostic.column == 5);
+            let expected = format!("{}:3:5: unclosed character class", test_env.gitignore.display());
+            assert!(err.to_string() == expected);
+        });
+
+        with_fake_repo("*.o\n/out\n", vec![], |test_env| {
+            assert!(File::new_strict(test_env.gitignore).unwrap().patterns().len() == 2);
+        });
+    }
+
+    #[test]
+    fn test_patterns_keep_source_location() {
+        with_fake_repo("*.foo\n\n# comment\n!bar.foo   ", vec![], |test_env| {
+            let file = File::new(test_env.gitignore).unwrap();
+            let patterns = file.patterns();
+
+            assert!(patterns.len() == 2);
+            assert!(patterns[0].line == 1);
+            assert!(patterns[0].text == b"*.foo");
+            assert!(patterns[1].line == 4);
+            assert!(patterns[1].text == b"!bar.foo   ");
+            assert!(patterns.iter().all(|pattern| pattern.source.as_deref() == Some(test_env.gitignore)));
+
+            // writing the patterns back out gives the same rules
+            let written: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+            let reparsed = File::from_str(&written.join("\n"), test_env.gitignore.parent().unwrap()).unwrap();
+            assert!(reparsed.patterns().iter().map(|pattern| &pattern.pattern)
+                .eq(patterns.iter().map(|pattern| &pattern.pattern)));
+            assert!(reparsed.patterns().iter().all(|pattern| pattern.source.is_none()));
+        })
+    }
+
+    #[test]
+    fn test_explain() {
+        with_fake_repo("# build output\n*.log\nbuild/\n!keep.log", vec![], |test_env| {
+            let file = File::new(test_env.gitignore).unwrap();
+            let root = test_env.gitignore.parent().unwrap();
+
+            let found = file.explain(Path::new("debug.log"), false).unwrap();
+            assert!(found.is_excluded());
+            assert!(found.source() == Some(test_env.gitignore));
+            assert!(found.line() == 2);
+            assert!(found.text() == b"*.log");
+            assert!(found.path == root.join("debug.log"));
+            assert!(found.to_string() == format!("{}:2:*.log\t{}", test_env.gitignore.display(),
+                                                 root.join("debug.log").display()));
+
+            let found = file.explain(Path::new("keep.log"), false).unwrap();
+            assert!(!found.is_excluded());
+            assert!(found.line() == 4);
+            assert!(found.text() == b"!keep.log");
+
+            assert!(file.explain(Path::new("src/lib.rs"), false).is_none());
+            assert!(file.explain(Path::new("/definitely/not/in/the/root.log"), false).is_none());
+        })
+    }
+
+    #[test]
+    fn test_explain_reports_excluded_parent() {
+        let file = File::from_str("build/\n!*.txt", Path::new("/wing")).unwrap();
+
+        // nothing inside an excluded directory can be re-included, so the directory's pattern wins
+        let found = file.explain(Path::new("build/notes.txt"), false).unwrap();
+        assert!(found.is_excluded());
+        assert!(found.source().is_none());
+        assert!(found.line() == 1);
+        assert!(found.path == Path::new("/wing/build"));
+        assert!(found.to_string() == ":1:build/\t/wing/build");
+    }
+
     #[test]
     fn test_included_files() {
         with_fake_repo("*.foo", vec!["bar.foo", "foo", "bar"], |test_env| {
@@ -193,6 +753,24 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_walk() {
+        with_fake_repo("woo\n*.foo", vec!["win", "bar.foo", "woo/hoo", "lux/box"], |test_env| {
+            let file = File::new(test_env.gitignore).unwrap();
+            let root = test_env.gitignore.parent().unwrap();
+            let entries: Vec<(PathBuf, bool, usize)> = file.walk().map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path().strip_prefix(root).unwrap().to_path_buf(), entry.is_dir(), entry.depth())
+            }).collect();
+
+            assert!(entries.len() == 4);
+            assert!(entries.contains(&(PathBuf::from(".gitignore"), false, 1)));
+            assert!(entries.contains(&(PathBuf::from("win"), false, 1)));
+            assert!(entries.contains(&(PathBuf::from("lux"), true, 1)));
+            assert!(entries.contains(&(PathBuf::from("lux/box"), false, 2)));
+        })
+    }
+
     #[test]
     fn test_nested_files() {
         with_fake_repo("woo", vec!["win", "woo/hoo", "woo/boo/shoo"], |test_env| {
@@ -228,6 +806,39 @@ mod tests {
             assert!(files.contains(&"bar.html".to_string()));
         })
     }
+
+    #[test]
+    fn test_cannot_reinclude_inside_excluded_directory() {
+        let cases = [
+            ("build/\n!build/keep.txt", true),
+            ("build\n!build/keep.txt", true),
+            ("build/\n!keep.txt", true),
+            ("/build/\n!/build/keep.txt", true),
+            ("build/\n!build/sub/keep.txt", true),
+            ("build/*\n!build/keep.txt", false),
+            ("/build/**\n!/build/keep.txt", false),
+            ("build/\n!build/\n!build/keep.txt", false),
+            ("*\n!*/\n!keep.txt", false)
+        ];
+
+        for &(contents, excluded) in &cases {
+            with_fake_repo(contents, vec!["build/keep.txt", "build/sub/keep.txt"], |test_env| {
+                let file = File::new(test_env.gitignore).unwrap();
+                let keep = Path::new("build/keep.txt");
+                let found = file.explain(keep, false).unwrap();
+                let included = file.included_files().unwrap();
+
+                assert!(file.is_excluded(keep).unwrap() == excluded);
+                assert!(file.matches_path(keep, false) == excluded);
+                assert!(found.is_excluded() == excluded);
+                assert!(included.contains(&test_env.paths[0]) != excluded);
+                if excluded {
+                    assert!(file.is_excluded(Path::new("build/sub/keep.txt")).unwrap());
+                }
+            })
+        }
+    }
+
     #[cfg(feature = "nightly")]
     #[bench]
     fn bench_new_file(b: &mut Bencher) {
@@ -254,7 +865,7 @@ mod tests {
 
         let paths = files.iter().map(|file| {
             let path = dir.path().join(file);
-            path.parent().map(|parent| fs::create_dir_all(&parent));
+            path.parent().map(fs::create_dir_all);
             write_to_file(&path, "");
             path
         }).collect();
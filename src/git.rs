@@ -0,0 +1,310 @@
+use error;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The parts of the process environment Git consults when locating the user's configuration.
+#[derive(Debug)]
+pub(crate) struct Env {
+    pub home: Option<PathBuf>,
+    pub xdg_config_home: Option<PathBuf>
+}
+
+impl Env {
+    /// Read the environment of the current process.
+    pub fn from_process() -> Env {
+        Env {
+            home: env::var_os("HOME").map(PathBuf::from),
+            xdg_config_home: env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        }
+    }
+
+    /// The directory Git uses for its XDG configuration, eg. `~/.config/git`.
+    fn xdg_git_dir(&self) -> Option<PathBuf> {
+        match self.xdg_config_home {
+            Some(ref xdg) if !xdg.as_os_str().is_empty() => Some(xdg.join("git")),
+            _ => self.home.as_ref().map(|home| home.join(".config").join("git"))
+        }
+    }
+}
+
+/// The settings from a repository's Git directory and configuration which affect how paths are
+/// ignored.
+#[derive(Debug)]
+pub(crate) struct Settings {
+    /// The paths of every excludes file that applies to the repository, ordered from lowest to
+    /// highest precedence: the user's `core.excludesFile` (or the XDG default if it is not
+    /// configured), followed by `$GIT_DIR/info/exclude`. None of the paths are guaranteed to
+    /// exist.
+    pub exclude_files: Vec<PathBuf>,
+    /// The value of `core.ignoreCase`, if it is set.
+    pub ignore_case: Option<bool>
+}
+
+impl Settings {
+    /// Load the settings for the repository whose worktree is rooted at `root`.
+    pub fn load(root: &Path, env: &Env) -> Result<Settings, error::Error> {
+        let common_dir = git_dir(root)?.map(|git_dir| common_dir(&git_dir));
+        let config = Config::load(common_dir.as_deref(), env)?;
+
+        let mut exclude_files = vec![];
+
+        let excludes_file = match config.get_path("core.excludesfile")? {
+            Some(path) => Some(expand_path(&path, root, env)),
+            None => env.xdg_git_dir().map(|dir| dir.join("ignore"))
+        };
+        exclude_files.extend(excludes_file);
+
+        if let Some(common_dir) = common_dir {
+            exclude_files.push(common_dir.join("info").join("exclude"));
+        }
+
+        Ok(Settings { exclude_files, ignore_case: config.get_bool("core.ignorecase") })
+    }
+}
+
+/// Locate the Git directory for the worktree at `root`. This is usually just `root/.git`, but a
+/// `.git` file (as used by linked worktrees and submodules) pointing at another directory is
+/// followed too.
+pub(crate) fn git_dir(root: &Path) -> Result<Option<PathBuf>, error::Error> {
+    let dot_git = root.join(".git");
+    if dot_git.is_dir() {
+        return Ok(Some(dot_git));
+    }
+
+    let contents = match read_optional(&dot_git)? {
+        Some(contents) => contents,
+        None => return Ok(None)
+    };
+
+    let gitdir = contents.split(|&byte| byte == b'\n').find_map(|line| line.strip_prefix(b"gitdir:"));
+    match gitdir {
+        Some(path) => Ok(Some(root.join(path_from_bytes(path.trim_ascii(), &dot_git)?))),
+        None => Ok(None)
+    }
+}
+
+/// Returns the directory holding the state shared between all worktrees, such as `info/exclude`
+/// and `config`. For linked worktrees this is named by the `commondir` file in the Git directory.
+fn common_dir(git_dir: &Path) -> PathBuf {
+    fs::read_to_string(git_dir.join("commondir"))
+        .map(|contents| git_dir.join(contents.trim()))
+        .unwrap_or_else(|_| git_dir.to_path_buf())
+}
+
+/// Expand a path read from the Git configuration: a leading `~/` is relative to the home
+/// directory, and other relative paths are taken to be relative to the root of the worktree.
+fn expand_path(path: &Path, root: &Path, env: &Env) -> PathBuf {
+    match (path.strip_prefix("~"), env.home.as_ref()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => root.join(path)
+    }
+}
+
+/// Read the contents of the file at `path`, returning `None` if it does not exist.
+fn read_optional(path: &Path) -> Result<Option<Vec<u8>>, error::Error> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(error::Error::io(err, path))
+    }
+}
+
+/// Turn a path read from the file at `source` into a path. On Unix this is just the bytes of the
+/// path, whatever their encoding.
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8], _source: &Path) -> Result<PathBuf, error::Error> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+/// Turn a path read from the file at `source` into a path. Paths are not bytes on other
+/// platforms, so it must be valid UTF-8, just as Git for Windows requires.
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8], source: &Path) -> Result<PathBuf, error::Error> {
+    match ::std::str::from_utf8(bytes) {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => Err(error::Error::invalid_utf8(source))
+    }
+}
+
+/// A minimal reader for Git configuration files, supporting just enough of the format to pull
+/// out simple values such as `core.excludesFile`. Include directives are not followed.
+///
+/// Like Git, the files are read as bytes, so a value in some other encoding, such as a Latin-1
+/// `user.name`, does no harm; a value is only decoded once it is asked for.
+#[derive(Debug, Default)]
+pub(crate) struct Config {
+    /// Every value found, in the order they were read, keyed by their lowercased `section.name`
+    /// (or `section.subsection.name`, where the subsection keeps its case), along with the file
+    /// each was read from.
+    values: Vec<(String, Vec<u8>, PathBuf)>
+}
+
+impl Config {
+    /// Load the global configuration files and then the repository's own configuration, so
+    /// values in the latter take precedence.
+    pub fn load(common_dir: Option<&Path>, env: &Env) -> Result<Config, error::Error> {
+        let mut paths = vec![];
+        paths.extend(env.xdg_git_dir().map(|dir| dir.join("config")));
+        paths.extend(env.home.as_ref().map(|home| home.join(".gitconfig")));
+        paths.extend(common_dir.map(|dir| dir.join("config")));
+
+        let mut config = Config::default();
+        for path in paths {
+            if let Some(contents) = read_optional(&path)? {
+                config.parse(&contents, &path);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the last value set for the given lowercased key, if any.
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.find(key).map(|(_, value, _)| value.as_slice())
+    }
+
+    /// Returns the last value set for the given lowercased key interpreted as a path, if any.
+    /// This fails only if the value cannot be a path on this platform.
+    pub fn get_path(&self, key: &str) -> Result<Option<PathBuf>, error::Error> {
+        self.find(key).map(|(_, value, source)| path_from_bytes(value, source)).transpose()
+    }
+
+    /// Returns the last value set for the given lowercased key interpreted as a boolean, if it is
+    /// set to something Git recognises as one.
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)?.to_ascii_lowercase().as_slice() {
+            b"true" | b"yes" | b"on" | b"1" => Some(true),
+            b"false" | b"no" | b"off" | b"0" | b"" => Some(false),
+            _ => None
+        }
+    }
+
+    /// Returns the last entry set for the given lowercased key, if any.
+    fn find(&self, key: &str) -> Option<&(String, Vec<u8>, PathBuf)> {
+        self.values.iter().rev().find(|(name, _, _)| name == key)
+    }
+
+    /// Parse the contents of a single configuration file, read from `source`, adding its values to
+    /// this config.
+    fn parse(&mut self, contents: &[u8], source: &Path) {
+        let mut section = String::new();
+
+        for line in contents.split(|&byte| byte == b'\n') {
+            let line = line.trim_ascii();
+
+            if line.starts_with(b"[") {
+                if let Some(end) = line.iter().position(|&byte| byte == b']') {
+                    section = Config::section(&String::from_utf8_lossy(&line[1..end]));
+                }
+                continue;
+            }
+
+            if line.is_empty() || line.starts_with(b"#") || line.starts_with(b";") {
+                continue;
+            }
+
+            let (name, value) = match line.iter().position(|&byte| byte == b'=') {
+                Some(index) => (&line[..index], Config::value(&line[index + 1..])),
+                // A name on its own is shorthand for a boolean set to true.
+                None => (line, b"true".to_vec())
+            };
+
+            let name = String::from_utf8_lossy(name.trim_ascii()).to_lowercase();
+            self.values.push((format!("{}.{}", section, name), value, source.to_path_buf()));
+        }
+    }
+
+    /// Normalise a section header, eg. `Core` or `remote "origin"`, into a key prefix.
+    fn section(header: &str) -> String {
+        match header.find('"') {
+            Some(index) => {
+                let name = header[..index].trim().to_lowercase();
+                let subsection = header[index + 1..].trim_end().trim_end_matches('"');
+                format!("{}.{}", name, subsection)
+            },
+            None => header.trim().to_lowercase()
+        }
+    }
+
+    /// Unquote and unescape a raw value, dropping any trailing comment.
+    fn value(raw: &[u8]) -> Vec<u8> {
+        let mut value = vec![];
+        let mut quoted = false;
+        let mut bytes = raw.trim_ascii().iter();
+
+        while let Some(&byte) = bytes.next() {
+            match byte {
+                b'"' => quoted = !quoted,
+                b'#' | b';' if !quoted => break,
+                b'\\' => match bytes.next() {
+                    Some(b'n') => value.push(b'\n'),
+                    Some(b't') => value.push(b'\t'),
+                    Some(&escaped) => value.push(escaped),
+                    None => {}
+                },
+                _ => value.push(byte)
+            }
+        }
+
+        value.trim_ascii_end().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    use std::path::Path;
+
+    #[test]
+    fn test_config_parse() {
+        let mut config = Config::default();
+        let contents = b"# comment\n[Core]\n\texcludesFile = \"~/my ignore\" ; trailing\n\tbare\n[remote \"Origin\"]\nurl = x\n";
+        config.parse(contents, Path::new("/wing/config"));
+
+        assert!(config.get("core.excludesfile") == Some(b"~/my ignore".as_ref()));
+        assert!(config.get("core.bare") == Some(b"true".as_ref()));
+        assert!(config.get("remote.Origin.url") == Some(b"x".as_ref()));
+        assert!(config.get("core.missing").is_none());
+    }
+
+    #[test]
+    fn test_config_get_bool() {
+        let mut config = Config::default();
+        let contents = b"[core]\nignoreCase = Yes\nbare\nfilemode = off\nsymlinks =\nother = maybe\n";
+        config.parse(contents, Path::new("/wing/config"));
+
+        assert!(config.get_bool("core.ignorecase") == Some(true));
+        assert!(config.get_bool("core.bare") == Some(true));
+        assert!(config.get_bool("core.filemode") == Some(false));
+        assert!(config.get_bool("core.symlinks") == Some(false));
+        assert!(config.get_bool("core.other").is_none());
+        assert!(config.get_bool("core.missing").is_none());
+    }
+
+    #[test]
+    fn test_config_last_value_wins() {
+        let mut config = Config::default();
+        config.parse(b"[core]\nexcludesfile = first\n", Path::new("/wing/config"));
+        config.parse(b"[core]\nexcludesfile = second\n", Path::new("/wing/config"));
+
+        assert!(config.get("core.excludesfile") == Some(b"second".as_ref()));
+    }
+
+    #[test]
+    fn test_config_values_are_bytes() {
+        let mut config = Config::default();
+        let contents = b"[user]\n\tname = Jos\xe9\n[core]\n\texcludesFile = caf\xe9\r\n";
+        config.parse(contents, Path::new("/wing/config"));
+
+        assert!(config.get("user.name") == Some(b"Jos\xe9".as_ref()));
+        assert!(config.get("core.excludesfile") == Some(b"caf\xe9".as_ref()));
+        assert!(config.get_path("core.missing").unwrap().is_none());
+    }
+}
@@ -6,20 +6,31 @@
 //!
 //! All of the patterns described in the [man page for the .gitignore format](https://www.kernel.org/pub/software/scm/git/docs/gitignore.html),
 //! (specifically, in the ["Pattern Format" section](https://www.kernel.org/pub/software/scm/git/docs/gitignore.html#_pattern_format))
-//! are implemented. This crate currently does not support auto-loading patterns from
-//! `$GIT_DIR/info/exclude` or from the file specified by the Git configuration variable
-//! `core.excludesFile` (the user excludes file); rather, it will only load patterns specified in
-//! the `.gitignore` file in the given directory.
+//! are implemented. A `File` loads the patterns from a single `.gitignore` file, whereas a
+//! `Repository` discovers every `.gitignore` file in a directory tree and applies them with the
+//! same precedence as Git. Opening a `Repository` with `Repository::open` additionally loads the
+//! patterns from `$GIT_DIR/info/exclude` and from the file specified by the Git configuration
+//! variable `core.excludesFile` (the user excludes file).
 
 #![cfg_attr(all(test, feature = "nightly"), feature(test))]
 
 #[cfg(all(test, feature = "nightly"))]
 extern crate test;
 
-pub use error::Error;
-pub use file::File;
-pub use pattern::Pattern;
+pub use error::{Error, ErrorKind};
+pub use file::{Diagnostic, File};
+pub use pattern::{Match, Pattern};
+pub use repository::Repository;
+pub use set::PatternSet;
+pub use walk::{DirEntry, Walk, WalkBuilder, WalkParallel};
 
 mod error;
 mod file;
+mod git;
 mod pattern;
+mod repository;
+mod set;
+#[cfg(test)]
+mod testing;
+mod walk;
+mod wildmatch;
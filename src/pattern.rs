@@ -1,49 +1,129 @@
-extern crate glob;
-
 use error;
+use wildmatch;
+
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use std::path::Path;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
 
 /// A pattern as found in a `.gitignore` file.
-#[derive(Debug)]
-pub struct Pattern<'c> {
-    /// The glob pattern after being parsed, negation or trailing directory slashes removed,
-    /// and the root prepended if anchored.
-    pub pattern: glob::Pattern,
-    /// Whether the pattern had the root prepended so the matches must be within the root
-    /// directory. That is to say, whether the pattern was anchored to the root.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    /// The glob after being parsed, with any negation, trailing directory slash or leading
+    /// anchoring slash removed. It is matched against paths relative to the root using the same
+    /// rules as Git's `wildmatch`. Like paths on Unix, patterns are bytes rather than text, so
+    /// they need not be valid UTF-8.
+    pub pattern: Vec<u8>,
+    /// Whether the pattern contained a slash at the beginning or in the middle, so the matches
+    /// must be relative to the root directory. That is to say, whether the pattern was anchored
+    /// to the root.
     pub anchored: bool,
     /// Whether the pattern should, if it matches, negate any previously matching patterns. This
     /// flag has no effect if no previous patterns had matched.
     pub negation: bool,
+    /// Whether the glob is matched case sensitively. This defaults to `true`, except on macOS
+    /// and Windows where filesystems are usually case insensitive.
+    pub case_sensitive: bool,
+    /// The pattern exactly as it was written, including any negation or trailing spaces, so that
+    /// it can be reported or written back out unchanged.
+    pub text: Vec<u8>,
+    /// The 1-based line number of the pattern within the file it was read from, or 0 if it was
+    /// created on its own with `Pattern::new`.
+    pub line: usize,
+    /// The file the pattern was read from, or `None` if it was created on its own or parsed from
+    /// a string or reader rather than read from a file.
+    pub source: Option<Arc<Path>>,
     directory: bool,
-    root: &'c Path
+    pub(crate) root: Arc<Path>
 }
 
-impl<'c> Pattern<'c> {
+impl Pattern {
     /// Create a new pattern from the raw glob as found in a `.gitignore` file.
     ///
+    /// A leading `!` negates the pattern. A pattern which really begins with a `!` or a `#` can be
+    /// written with a backslash in front of the first character, eg. `\!important!.txt`. Note
+    /// that comments are not recognised here, as they are a property of lines in a file rather
+    /// than of patterns.
+    ///
+    /// Trailing spaces are ignored unless they are escaped with a backslash, eg. `foo\ `. Leading
+    /// spaces are not, even after a `!`, so `! foo` re-includes a file named ` foo`, as in Git.
+    ///
+    /// Two consecutive asterisks are special when they make up a whole component of the pattern:
+    /// a leading `**/` matches in all directories, a trailing `/**` matches everything inside a
+    /// directory, and a `/**/` in the middle matches zero or more directories. Any other run of
+    /// asterisks is treated like a single `*`.
+    ///
+    /// An error is returned if the glob can never match anything, such as when a character class
+    /// is never closed.
+    ///
+    /// The value of `root` must be an absolute path.
+    pub fn new(raw_pattern: &str, root: &Path) -> Result<Pattern, error::Error> {
+        Pattern::from_bytes(raw_pattern.as_bytes(), root)
+    }
+
+    /// Create a new pattern from the raw glob exactly as `new` does, but from bytes which need
+    /// not be valid UTF-8, as they may be in a `.gitignore` file on Unix.
+    ///
     /// The value of `root` must be an absolute path.
-    pub fn new(raw_pattern: &str, root: &'c Path) -> Result<Pattern<'c>, error::Error> {
-        let mut parsed_pattern = raw_pattern.to_string();
-        let directory = parsed_pattern.ends_with('/');
+    pub fn from_bytes(raw_pattern: &[u8], root: &Path) -> Result<Pattern, error::Error> {
+        if !root.is_absolute() {
+            return Err(error::Error::not_absolute(root));
+        }
+
+        Pattern::with_root(raw_pattern, Arc::from(root)).map_err(|mut err| {
+            err.pos = char_offset(raw_pattern, err.pos);
+            err.into()
+        })
+    }
+
+    /// Create a new pattern from the raw glob as found in a `.gitignore` file, sharing the given
+    /// root with any other patterns from the same file. The position of any error is a byte offset
+    /// into `raw_pattern`.
+    pub(crate) fn with_root(raw_pattern: &[u8], root: Arc<Path>)
+        -> Result<Pattern, wildmatch::PatternError> {
+        let trimmed = trim_trailing_spaces(raw_pattern);
+        let escaped = trimmed.starts_with(b"\\!") || trimmed.starts_with(b"\\#");
+        let negation = !escaped && trimmed.starts_with(b"!");
+
+        // An escaped `!` or `#` keeps its backslash, as the glob engine treats it as a literal.
+        let glob = if negation { &trimmed[1..] } else { trimmed };
+        let mut offset = trimmed.len() - glob.len();
+        let mut parsed_pattern = glob.to_vec();
+
+        let directory = parsed_pattern.ends_with(b"/");
 
         if directory {
             parsed_pattern.pop();
         }
 
-        let anchored = parsed_pattern.contains('/');
-        let negation = parsed_pattern.starts_with('!');
+        // Once any trailing separator has been removed, a separator at the beginning or in the
+        // middle of the pattern anchors it to the directory containing the `.gitignore` file.
+        let anchored = parsed_pattern.contains(&b'/');
 
-        if negation {
+        if parsed_pattern.starts_with(b"/") {
             parsed_pattern.remove(0);
-            parsed_pattern = parsed_pattern.trim_start().to_string();
+            offset += 1;
         }
 
-        let abs_pattern = Pattern::abs_pattern(&parsed_pattern, root, anchored);
-        let pattern = glob::Pattern::new(&abs_pattern)?;
-
-        Ok(Pattern { pattern, anchored, negation, directory, root })
+        wildmatch::validate(&parsed_pattern)
+            .map_err(|err| wildmatch::PatternError { pos: err.pos + offset, ..err })?;
+
+        let case_sensitive = CASE_SENSITIVE_BY_DEFAULT;
+
+        Ok(Pattern {
+            pattern: parsed_pattern,
+            anchored,
+            negation,
+            case_sensitive,
+            text: raw_pattern.to_vec(),
+            line: 0,
+            source: None,
+            directory,
+            root
+        })
     }
 
     /// Returns true if the given path is matched by the current pattern, and hence would be
@@ -51,67 +131,172 @@ impl<'c> Pattern<'c> {
     /// representing whether the given path is a directory - if so, it should be set to `true`,
     /// otherwise `false` if not (eg. file, special file, symlink).
     ///
-    /// Note that if the glob was negated (ie. of the format `! some/glob/*.here`) then this will
+    /// Note that if the glob was negated (ie. of the format `!some/glob/*.here`) then this will
     /// return the opposite value, eg. `false` if the pattern matched, and `true` if the pattern
     /// did not match.
     ///
-    /// The value of `path` must be an absolute path.
+    /// Only the path itself is checked, not its parent directories, so this cannot tell whether
+    /// the path lies inside an excluded directory; `File::is_excluded` takes care of that.
+    ///
+    /// If the value for `path` is not absolute, it will be assumed to be relative to the root.
     pub fn is_excluded(&self, path: &Path, directory: bool) -> bool {
+        // XOR the two together to calculate the match.
+        self.negation ^ self.matches(path, directory)
+    }
+
+    /// Returns true if the given path is matched by the glob of the current pattern, regardless
+    /// of whether the pattern was negated. The second argument, `directory`, is a `bool`
+    /// representing whether the given path is a directory.
+    ///
+    /// As in Git, an unanchored pattern is matched against just the final component of the path,
+    /// whereas an anchored pattern is matched against the whole path relative to the root, with
+    /// wildcards other than `**` unable to match a slash.
+    ///
+    /// If the value for `path` is not absolute, it will be assumed to be relative to the root.
+    pub fn matches(&self, path: &Path, directory: bool) -> bool {
         if self.directory && !directory {
-            return self.negation
+            return false
         }
 
-        // XOR the two together to calculate the match.
-        self.negation ^ self.pattern.matches_path_with(&path, self.match_options())
-    }
+        // A pattern can never match anything outside of the directory it was loaded from.
+        let abs_path = self.root.join(path);
+        let relative = match abs_path.strip_prefix(&self.root) {
+            Ok(relative) => relative,
+            Err(_) => return false
+        };
+
+        let casefold = !self.case_sensitive;
 
-    /// Take the given pattern as a glob, and if anchoring is required, join the given root to the
-    /// beginning of the pattern. If the glob is unanchored, instead prepend a wildcard.
-    fn abs_pattern(pattern: &str, root: &Path, anchored: bool) -> String {
-        if anchored {
-            Pattern::abs_pattern_anchored(pattern, root)
-        } else if !pattern.starts_with('*') {
-            Pattern::abs_pattern_unanchored(pattern)
+        let (text, flags) = if self.anchored {
+            (path_bytes(relative), wildmatch::Flags { casefold, pathname: true })
         } else {
-            pattern.to_string()
-        }
+            match relative.file_name() {
+                Some(name) => (path_bytes(Path::new(name)), wildmatch::Flags { casefold, pathname: false }),
+                None => return false
+            }
+        };
+
+        wildmatch::wildmatch(&self.pattern, &text, flags)
+    }
+}
+
+impl fmt::Display for Pattern {
+    /// Writes the pattern exactly as it was originally written.
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str(&String::from_utf8_lossy(&self.text))
+    }
+}
+
+/// The pattern which decided whether a path is excluded, as returned by `File::explain` and
+/// `Repository::explain`.
+///
+/// When displayed, this is formatted like the output of `git check-ignore -v`, ie. the source
+/// file, line number and text of the pattern separated by colons, then a tab and the path.
+#[derive(Debug)]
+pub struct Match<'a> {
+    /// The last pattern to match. If it is negated, the path was explicitly re-included.
+    pub pattern: &'a Pattern,
+    /// The path the pattern matched. This is either the path being explained, or one of its
+    /// parent directories if that directory was excluded.
+    pub path: PathBuf
+}
+
+impl<'a> Match<'a> {
+    /// Returns true if the match means the path is excluded, ie. the pattern was not negated.
+    pub fn is_excluded(&self) -> bool {
+        !self.pattern.negation
     }
 
-    /// Given an anchored pattern, join the given root to the beginning of the pattern.
-    fn abs_pattern_anchored(pattern: &str, root: &Path) -> String {
-        let mut root_path = root.to_str().unwrap().to_string();
+    /// The file the pattern was read from, or `None` if it was parsed from a string or reader.
+    pub fn source(&self) -> Option<&'a Path> {
+        self.pattern.source.as_deref()
+    }
 
-        if root_path.ends_with('/') {
-            root_path.pop();
-        }
+    /// The 1-based line number of the pattern within the file it was read from.
+    pub fn line(&self) -> usize {
+        self.pattern.line
+    }
 
-        root_path + pattern
+    /// The pattern exactly as it was written in the file.
+    pub fn text(&self) -> &'a [u8] {
+        &self.pattern.text
     }
 
-    /// Given an an unanchored pattern, prepend a wildcard.
-    fn abs_pattern_unanchored(pattern: &str) -> String {
-        let wildcard = "*".to_string();
-        wildcard + pattern
+    /// The pattern as Git reports it, eg. in the output of `git check-ignore -v`: as it was written,
+    /// including any leading `!` or trailing `/`, but without trailing spaces which are not escaped.
+    pub fn trimmed_text(&self) -> &'a [u8] {
+        trim_trailing_spaces(&self.pattern.text)
     }
+}
+
+impl<'a> fmt::Display for Match<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let source = self.source().map(|source| source.display().to_string()).unwrap_or_default();
+        let text = String::from_utf8_lossy(self.trimmed_text());
+        write!(f, "{}:{}:{}\t{}", source, self.line(), text, self.path.display())
+    }
+}
+
+/// Returns the given relative path as bytes separated by forward slashes, the way patterns are
+/// written. On Unix this is just the bytes of the path, whatever their encoding.
+#[cfg(unix)]
+pub(crate) fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+/// Returns the given relative path as bytes separated by forward slashes, the way patterns are
+/// written. Paths are not bytes on other platforms, so they are matched as UTF-8, just as Git for
+/// Windows does.
+#[cfg(not(unix))]
+pub(crate) fn path_bytes(path: &Path) -> Cow<'_, [u8]> {
+    Cow::Owned(path.to_string_lossy().replace('\\', "/").into_bytes())
+}
+
+/// Returns the number of characters in the first `pos` bytes of the given text, treating any
+/// invalid UTF-8 sequence as a single character.
+pub(crate) fn char_offset(text: &[u8], pos: usize) -> usize {
+    String::from_utf8_lossy(&text[..pos]).chars().count()
+}
 
-    /// Return the options that should be given to the glob match function, taking into account
-    /// whether the current pattern is anchored or unanchored so as to apply the glob options
-    /// described in the ["Pattern Format" section](https://www.kernel.org/pub/software/scm/git/docs/gitignore.html#_pattern_format))
-    /// of the man pages on the `.gitignore` format.
-    fn match_options(&self) -> glob::MatchOptions {
-        glob::MatchOptions {
-            case_sensitive: false,
-            require_literal_separator: self.anchored,
-            require_literal_leading_dot: false
+/// Whether patterns are matched case sensitively by default. Git itself matches case sensitively
+/// unless `core.ignoreCase` is set, which it does automatically when a repository is created on a
+/// case insensitive filesystem, as is usual on macOS and Windows.
+pub(crate) const CASE_SENSITIVE_BY_DEFAULT: bool = !cfg!(any(target_os = "macos", windows));
+
+/// Strip any trailing spaces from a line, unless they are escaped with a backslash. Only spaces
+/// are stripped; other whitespace such as tabs is significant, exactly as in Git.
+pub(crate) fn trim_trailing_spaces(line: &[u8]) -> &[u8] {
+    let mut last_space = None;
+    let mut bytes = line.iter().enumerate();
+
+    while let Some((index, &b)) = bytes.next() {
+        match b {
+            b' ' => {
+                if last_space.is_none() {
+                    last_space = Some(index);
+                }
+            },
+            b'\\' => {
+                // A trailing backslash escapes nothing, so leave the line untouched.
+                if bytes.next().is_none() {
+                    return line;
+                }
+                last_space = None;
+            },
+            _ => last_space = None
         }
     }
+
+    match last_space {
+        Some(index) => &line[..index],
+        None => line
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    extern crate glob;
-
-    use super::Pattern;
+    use super::{Match, Pattern, trim_trailing_spaces};
+    use error::ErrorKind;
     use std::path::Path;
 
     #[cfg(feature = "nightly")]
@@ -120,46 +305,186 @@ mod tests {
     #[test]
     fn test_new_git_ignore_pattern() {
         let gip = Pattern::new("*.foo", Path::new("/wing")).unwrap();
-        assert!(gip.pattern == glob::Pattern::new("*.foo").unwrap());
-        assert!(gip.anchored == false);
-        assert!(gip.directory == false);
-        assert!(gip.negation == false);
+        assert!(gip.pattern == b"*.foo");
+        assert!(!gip.anchored);
+        assert!(!gip.directory);
+        assert!(!gip.negation);
     }
 
     #[test]
     fn test_new_anchored_git_ignore_pattern() {
         let gip = Pattern::new("/*.foo", Path::new("/wing")).unwrap();
-        assert!(gip.pattern == glob::Pattern::new("/wing/*.foo").unwrap());
-        assert!(gip.anchored == true);
-        assert!(gip.directory == false);
-        assert!(gip.negation == false);
+        assert!(gip.pattern == b"*.foo");
+        assert!(gip.anchored);
+        assert!(!gip.directory);
+        assert!(!gip.negation);
     }
 
     #[test]
     fn test_new_anchored_git_ignore_pattern_with_trailing_slash_on_root() {
         let gip = Pattern::new("/*.foo", Path::new("/wing/")).unwrap();
-        assert!(gip.pattern == glob::Pattern::new("/wing/*.foo").unwrap());
-        assert!(gip.anchored == true);
-        assert!(gip.directory == false);
-        assert!(gip.negation == false);
+        assert!(gip.pattern == b"*.foo");
+        assert!(gip.anchored);
+        assert!(!gip.directory);
+        assert!(!gip.negation);
+    }
+
+    #[test]
+    fn test_new_middle_slash_git_ignore_pattern() {
+        let gip = Pattern::new("doc/frotz", Path::new("/wing")).unwrap();
+        assert!(gip.pattern == b"doc/frotz");
+        assert!(gip.anchored);
+        assert!(!gip.directory);
+        assert!(!gip.negation);
+    }
+
+    #[test]
+    fn test_new_negated_middle_slash_git_ignore_pattern() {
+        let gip = Pattern::new("!doc/frotz/", Path::new("/wing/")).unwrap();
+        assert!(gip.pattern == b"doc/frotz");
+        assert!(gip.anchored);
+        assert!(gip.directory);
+        assert!(gip.negation);
+    }
+
+    #[test]
+    fn test_new_anchored_git_ignore_pattern_with_glob_characters_in_root() {
+        let gip = Pattern::new("/foo", Path::new("/wi[ng]")).unwrap();
+        assert!(gip.pattern == b"foo");
+        assert!(gip.is_excluded(Path::new("/wi[ng]/foo"), false));
+        assert!(!gip.is_excluded(Path::new("/win/foo"), false));
     }
 
     #[test]
     fn test_new_directory_git_ignore_pattern() {
         let gip = Pattern::new("foo/", Path::new("/wing")).unwrap();
-        assert!(gip.pattern == glob::Pattern::new("*foo").unwrap());
-        assert!(gip.anchored == false);
-        assert!(gip.directory == true);
-        assert!(gip.negation == false);
+        assert!(gip.pattern == b"foo");
+        assert!(!gip.anchored);
+        assert!(gip.directory);
+        assert!(!gip.negation);
     }
 
     #[test]
     fn test_new_negated_git_ignore_pattern() {
-        let gip = Pattern::new("! *.foo", Path::new("/wing")).unwrap();
-        assert!(gip.pattern == glob::Pattern::new("*.foo").unwrap());
-        assert!(gip.anchored == false);
-        assert!(gip.directory == false);
-        assert!(gip.negation == true);
+        let gip = Pattern::new("!*.foo", Path::new("/wing")).unwrap();
+        assert!(gip.pattern == b"*.foo");
+        assert!(!gip.anchored);
+        assert!(!gip.directory);
+        assert!(gip.negation);
+    }
+
+    #[test]
+    fn test_new_escaped_negation_git_ignore_pattern() {
+        let gip = Pattern::new("\\!important!.txt", Path::new("/wing")).unwrap();
+        assert!(gip.pattern == b"\\!important!.txt");
+        assert!(!gip.negation);
+    }
+
+    #[test]
+    fn test_new_escaped_hash_git_ignore_pattern() {
+        let gip = Pattern::new("\\#foo", Path::new("/wing")).unwrap();
+        assert!(gip.pattern == b"\\#foo");
+        assert!(!gip.negation);
+    }
+
+    #[test]
+    fn test_new_keeps_original_text() {
+        let gip = Pattern::new("!/doc/frotz/  ", Path::new("/wing")).unwrap();
+        assert!(gip.pattern == b"doc/frotz");
+        assert!(gip.text == b"!/doc/frotz/  ");
+        assert!(gip.to_string() == "!/doc/frotz/  ");
+        assert!(gip.line == 0);
+        assert!(gip.source.is_none());
+    }
+
+    #[test]
+    fn test_matches_escaped_negation() {
+        // returns true when given a path beginning with a literal "!" escaped in the pattern
+        let gip = Pattern::new("\\!important!.txt", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("!important!.txt"), false));
+        assert!(!gip.is_excluded(Path::new("important!.txt"), false));
+    }
+
+    #[test]
+    fn test_matches_escaped_hash() {
+        // returns true when given a path beginning with a literal "#" escaped in the pattern
+        let gip = Pattern::new("\\#foo", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("#foo"), false));
+    }
+
+    #[test]
+    fn test_new_with_relative_root() {
+        let err = Pattern::new("foo", Path::new("wing")).unwrap_err();
+        assert!(err.kind() == ErrorKind::NotAbsolute);
+        assert!(err.path() == Some(Path::new("wing")));
+    }
+
+    #[test]
+    fn test_trim_trailing_spaces() {
+        assert!(trim_trailing_spaces(b"foo") == b"foo");
+        assert!(trim_trailing_spaces(b"foo   ") == b"foo");
+        assert!(trim_trailing_spaces(b"foo\\ ") == b"foo\\ ");
+        assert!(trim_trailing_spaces(b"foo\\  ") == b"foo\\ ");
+        assert!(trim_trailing_spaces(b"foo \\ ") == b"foo \\ ");
+        assert!(trim_trailing_spaces(b"foo\t") == b"foo\t");
+        assert!(trim_trailing_spaces(b"foo\\") == b"foo\\");
+        assert!(trim_trailing_spaces(b"   ").is_empty());
+    }
+
+    #[test]
+    fn test_match_trimmed_text() {
+        let gip = Pattern::new("!foo/  ", Path::new("/wing")).unwrap();
+        let found = Match { pattern: &gip, path: Path::new("/wing/foo").to_path_buf() };
+        assert!(found.text() == b"!foo/  ");
+        assert!(found.trimmed_text() == b"!foo/");
+        assert!(found.to_string() == ":0:!foo/\t/wing/foo");
+
+        let gip = Pattern::new("foo\\  ", Path::new("/wing")).unwrap();
+        let found = Match { pattern: &gip, path: Path::new("/wing/foo ").to_path_buf() };
+        assert!(found.trimmed_text() == b"foo\\ ");
+    }
+
+    #[test]
+    fn test_matches_trailing_spaces_stripped() {
+        let gip = Pattern::new("foo   ", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("foo"), false));
+        assert!(!gip.is_excluded(Path::new("foo   "), false));
+    }
+
+    #[test]
+    fn test_matches_escaped_trailing_space() {
+        let gip = Pattern::new("foo\\ ", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("foo "), false));
+        assert!(!gip.is_excluded(Path::new("foo"), false));
+
+        let gip = Pattern::new("foo\\ \\  ", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("foo  "), false));
+        assert!(!gip.is_excluded(Path::new("foo "), false));
+    }
+
+    #[test]
+    fn test_matches_directory_with_trailing_spaces() {
+        let gip = Pattern::new("foo/  ", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("foo"), true));
+        assert!(!gip.is_excluded(Path::new("foo"), false));
+    }
+
+    #[test]
+    fn test_matches_case_sensitive() {
+        let mut gip = Pattern::new("foo.o", Path::new("/")).unwrap();
+        gip.case_sensitive = true;
+        assert!(gip.is_excluded(Path::new("foo.o"), false));
+        assert!(!gip.is_excluded(Path::new("Foo.o"), false));
+
+        gip.case_sensitive = false;
+        assert!(gip.is_excluded(Path::new("Foo.o"), false));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_new_is_case_sensitive_on_linux() {
+        let gip = Pattern::new("foo.o", Path::new("/")).unwrap();
+        assert!(gip.case_sensitive);
     }
 
     #[test]
@@ -179,10 +504,20 @@ mod tests {
     #[test]
     fn test_matches_negated() {
         // returns false when given a path that has a negation pattern
-        let gip = Pattern::new("! foo", Path::new("/")).unwrap();
+        let gip = Pattern::new("!foo", Path::new("/")).unwrap();
         assert!(!gip.is_excluded(Path::new("foo"), false));
     }
 
+    #[test]
+    fn test_matches_negated_keeps_leading_space() {
+        // as in Git, only the `!` is removed, so the space is part of the name to re-include
+        let gip = Pattern::new("! foo", Path::new("/")).unwrap();
+        assert!(gip.negation && gip.pattern == b" foo");
+        assert!(!gip.matches(Path::new("foo"), false));
+        assert!(gip.matches(Path::new(" foo"), false));
+        assert!(!gip.is_excluded(Path::new(" foo"), false));
+    }
+
     #[test]
     fn test_matches_unanchored_on_nested_file() {
         // returns true when given a nested path that matches an unanchored wildcard pattern
@@ -211,6 +546,34 @@ mod tests {
         assert!(!gip.is_excluded(Path::new("foo/bar/lux"), false));
     }
 
+    #[test]
+    fn test_matches_middle_slash_anchored() {
+        // returns true only when the path is relative to the root, as "doc/frotz" is anchored
+        let gip = Pattern::new("doc/frotz", Path::new("/wing")).unwrap();
+        assert!(gip.is_excluded(Path::new("doc/frotz"), true));
+        assert!(gip.is_excluded(Path::new("doc/frotz"), false));
+        assert!(!gip.is_excluded(Path::new("/wing/a/doc/frotz"), true));
+        assert!(!gip.is_excluded(Path::new("/wingdoc/frotz"), true));
+    }
+
+    #[test]
+    fn test_matches_leading_and_middle_slash_equivalent() {
+        let leading = Pattern::new("/doc/frotz", Path::new("/wing")).unwrap();
+        let middle = Pattern::new("doc/frotz", Path::new("/wing")).unwrap();
+        for path in &["doc/frotz", "/wing/a/doc/frotz", "/wing/frotz"] {
+            assert!(leading.is_excluded(Path::new(path), false) == middle.is_excluded(Path::new(path), false));
+        }
+    }
+
+    #[test]
+    fn test_matches_trailing_slash_not_anchored() {
+        // "frotz/" matches a directory named frotz at any level
+        let gip = Pattern::new("frotz/", Path::new("/wing")).unwrap();
+        assert!(!gip.anchored);
+        assert!(gip.is_excluded(Path::new("/wing/frotz"), true));
+        assert!(gip.is_excluded(Path::new("/wing/a/frotz"), true));
+    }
+
     #[test]
     fn test_matches_directory_on_directory() {
         // returns true when given a path that is a directory and it matches a directory only pattern
@@ -249,6 +612,109 @@ mod tests {
         assert!(!gip.is_excluded(Path::new("foo/bar/index.html"), false));
     }
 
+    #[test]
+    fn test_leading_double_star_matches_in_all_directories() {
+        let gip = Pattern::new("**/foo", Path::new("/wing")).unwrap();
+        assert!(gip.is_excluded(Path::new("foo"), false));
+        assert!(gip.is_excluded(Path::new("a/foo"), false));
+        assert!(gip.is_excluded(Path::new("/wing/a/b/foo"), true));
+        assert!(!gip.is_excluded(Path::new("a/xfoo"), false));
+        assert!(!gip.is_excluded(Path::new("foo/a"), false));
+    }
+
+    #[test]
+    fn test_leading_double_star_with_nested_path() {
+        // "**/foo/bar" matches "bar" anywhere directly beneath a directory "foo"
+        let gip = Pattern::new("**/foo/bar", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("foo/bar"), false));
+        assert!(gip.is_excluded(Path::new("a/b/foo/bar"), false));
+        assert!(!gip.is_excluded(Path::new("foo/a/bar"), false));
+    }
+
+    #[test]
+    fn test_trailing_double_star_matches_everything_inside() {
+        let gip = Pattern::new("abc/**", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("abc/x"), false));
+        assert!(gip.is_excluded(Path::new("abc/x/y"), true));
+        assert!(!gip.is_excluded(Path::new("abc"), true));
+        assert!(!gip.is_excluded(Path::new("abc"), false));
+        // the pattern contains a slash, so it is anchored to the root
+        assert!(!gip.is_excluded(Path::new("x/abc/y"), false));
+    }
+
+    #[test]
+    fn test_middle_double_star_matches_zero_or_more_directories() {
+        let gip = Pattern::new("a/**/b", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("a/b"), false));
+        assert!(gip.is_excluded(Path::new("a/x/b"), false));
+        assert!(gip.is_excluded(Path::new("a/x/y/b"), false));
+        assert!(!gip.is_excluded(Path::new("a/xb"), false));
+        assert!(!gip.is_excluded(Path::new("ab"), false));
+        assert!(!gip.is_excluded(Path::new("x/a/b"), false));
+    }
+
+    #[test]
+    fn test_double_star_alone_matches_everything() {
+        let gip = Pattern::new("**", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("foo"), false));
+        assert!(gip.is_excluded(Path::new("a/b/foo"), true));
+    }
+
+    #[test]
+    fn test_other_consecutive_stars_are_regular() {
+        // "**" which is not a whole component does not match across directories
+        let gip = Pattern::new("a/**b", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("a/xb"), false));
+        assert!(!gip.is_excluded(Path::new("a/x/yb"), false));
+
+        let gip = Pattern::new("a/x**/b", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("a/xy/b"), false));
+        assert!(!gip.is_excluded(Path::new("a/x/y/b"), false));
+    }
+
+    #[test]
+    fn test_matches_unanchored_whole_name_only() {
+        // an unanchored pattern must match the whole of the final component, not just its end
+        let gip = Pattern::new("foo", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("bar/foo"), false));
+        assert!(!gip.is_excluded(Path::new("xfoo"), false));
+        assert!(!gip.is_excluded(Path::new("foo/bar"), false));
+    }
+
+    #[test]
+    fn test_matches_anchored_star_does_not_cross_slash() {
+        let gip = Pattern::new("doc/*.txt", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("doc/notes.txt"), false));
+        assert!(!gip.is_excluded(Path::new("doc/server/notes.txt"), false));
+    }
+
+    #[test]
+    fn test_matches_negated_character_class() {
+        for raw in &["[!a]*.o", "[^a]*.o"] {
+            let gip = Pattern::new(raw, Path::new("/")).unwrap();
+            assert!(gip.is_excluded(Path::new("foo.o"), false));
+            assert!(!gip.is_excluded(Path::new("afoo.o"), false));
+        }
+    }
+
+    #[test]
+    fn test_matches_escaped_glob_characters() {
+        let gip = Pattern::new("\\*\\?\\[x]", Path::new("/")).unwrap();
+        assert!(gip.is_excluded(Path::new("*?[x]"), false));
+        assert!(!gip.is_excluded(Path::new("a?x"), false));
+    }
+
+    #[test]
+    fn test_new_invalid_git_ignore_pattern() {
+        let err = Pattern::new("foo[", Path::new("/")).unwrap_err();
+        assert!(err.kind() == ErrorKind::InvalidPattern { line: 0, column: 4 });
+        assert!(err.to_string() == "Pattern syntax error near position 3: unclosed character class");
+
+        // the position is given relative to the raw pattern, before negation is removed
+        let err = Pattern::new("!/foo\\", Path::new("/")).unwrap_err();
+        assert!(err.to_string() == "Pattern syntax error near position 5: trailing backslash escapes nothing");
+    }
+
     #[cfg(feature = "nightly")]
     #[bench]
     fn bench_pattern_new(b: &mut Bencher) {
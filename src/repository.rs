@@ -0,0 +1,866 @@
+use error;
+use file;
+use git;
+use pattern;
+use set;
+use walk;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
+
+/// Represents a whole tree of `.gitignore` files, rooted at the top of a repository. Use this to
+/// discover every `.gitignore` file in the tree and check if a given path would be excluded by
+/// the rules they contain, applying them with the same precedence as Git.
+///
+/// Patterns in a `.gitignore` file are relative to the directory containing that file, and
+/// patterns in deeper files take precedence over those in shallower files. When opened with
+/// `Repository::open`, the patterns in `$GIT_DIR/info/exclude` and the user's excludes file are
+/// loaded too, beneath those of every `.gitignore` file.
+///
+/// Decisions about directories are remembered, along with the patterns which could match
+/// anything directly inside each of them, so that checking many paths in the same directories, or
+/// walking the tree, does not work them out again for every path. Only so many directories are
+/// remembered at once, so a long-lived repository which sees many paths does not grow without
+/// bound. Use `refresh` to pick up any changes made to the ignore files since the repository was
+/// loaded.
+///
+/// # Examples
+///
+/// ```
+/// # use std::env;
+/// # let pwd = env::current_dir().unwrap();
+/// let repository = gitignore::Repository::new(&pwd).unwrap();
+/// # let path_to_test_if_excluded = pwd.join("target");
+/// assert!(repository.is_excluded(&path_to_test_if_excluded).unwrap())
+/// ```
+#[derive(Debug)]
+pub struct Repository {
+    /// Files of patterns which apply to the whole repository, such as `$GIT_DIR/info/exclude`,
+    /// ordered from lowest to highest precedence.
+    excludes: Vec<file::File>,
+    /// The paths the repository-wide excludes files were looked for at, whether or not they exist.
+    exclude_paths: Vec<PathBuf>,
+    files: BTreeMap<Arc<Path>, file::File>,
+    /// Every path an ignore file was looked for at, along with what was found there, so that
+    /// `refresh` can tell whether any of them have changed.
+    stamps: Vec<(PathBuf, Option<Stamp>)>,
+    cache: RwLock<Cache>,
+    root: Arc<Path>,
+    case_sensitive: bool
+}
+
+/// The modification time and size of a file, which change whenever the file is written to.
+type Stamp = (Option<SystemTime>, u64);
+
+/// How many directories a `Cache` remembers before it forgets them all and starts again.
+const CACHE_LIMIT: usize = 1 << 16;
+
+/// Decisions remembered about directories, so that they need not be worked out again for every
+/// path inside them. They are forgotten whenever the patterns change, or once `CACHE_LIMIT`
+/// directories are remembered.
+#[derive(Debug, Default)]
+struct Cache {
+    /// Whether each directory, or any directory above it, is excluded.
+    excluded: HashMap<PathBuf, bool>,
+    /// The rules for the paths directly inside each directory.
+    rules: HashMap<PathBuf, Arc<Rules>>,
+    /// Every distinct set of rules built so far, by the patterns in it, so that the many
+    /// directories whose rules are the same share a single set.
+    shared: HashMap<Vec<Origin>, Arc<Rules>>
+}
+
+impl Cache {
+    /// Forget everything if adding another directory would take the cache over its limit.
+    fn make_room(&mut self) {
+        if self.excluded.len() + self.rules.len() >= CACHE_LIMIT {
+            *self = Cache::default();
+        }
+    }
+}
+
+/// Every pattern from the files which apply to the paths directly inside a directory that could
+/// match any of those paths, merged into a single set in order of increasing precedence.
+#[derive(Debug)]
+struct Rules {
+    set: set::PatternSet,
+    /// Where each pattern in the set was taken from, by its index in the set.
+    origins: Vec<Origin>
+}
+
+/// Identifies a pattern loaded by a repository: the file it was read from, and its index within
+/// that file.
+type Origin = (Source, usize);
+
+/// A file of patterns loaded by a repository.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Source {
+    /// One of the repository-wide excludes files, by its index in `Repository::excludes`.
+    Exclude(usize),
+    /// The `.gitignore` file in the given directory.
+    Gitignore(Arc<Path>)
+}
+
+impl Repository {
+    /// Walk the given directory, loading every `.gitignore` file found in it or any of its
+    /// subdirectories. Directories which are excluded are not descended into, as Git would never
+    /// read the `.gitignore` files within them.
+    ///
+    /// The value of `root` must be an absolute path.
+    pub fn new(root: &Path) -> Result<Repository, error::Error> {
+        if !root.is_absolute() {
+            return Err(error::Error::not_absolute(root));
+        }
+
+        Repository::with_excludes(Arc::from(root), vec![], pattern::CASE_SENSITIVE_BY_DEFAULT)
+    }
+
+    /// Open the Git repository whose worktree is rooted at the given directory. As well as every
+    /// `.gitignore` file, this loads the patterns from `$GIT_DIR/info/exclude` and from the file
+    /// named by the `core.excludesFile` configuration variable, falling back to
+    /// `$XDG_CONFIG_HOME/git/ignore` if that is not set. The `.git` directory may also be a
+    /// `.git` file pointing elsewhere, as with linked worktrees.
+    ///
+    /// If the `core.ignoreCase` configuration variable is set, patterns are matched case
+    /// sensitively or not accordingly.
+    ///
+    /// The configuration is read from the repository itself, `~/.gitconfig` and
+    /// `$XDG_CONFIG_HOME/git/config`. Any of these files, and any of the excludes files, may be
+    /// missing.
+    ///
+    /// The value of `root` must be an absolute path.
+    pub fn open(root: &Path) -> Result<Repository, error::Error> {
+        Repository::open_with(root, &git::Env::from_process())
+    }
+
+    /// Open the Git repository at the given directory, using the given environment to locate the
+    /// user's configuration.
+    fn open_with(root: &Path, env: &git::Env) -> Result<Repository, error::Error> {
+        if !root.is_absolute() {
+            return Err(error::Error::not_absolute(root));
+        }
+
+        let root: Arc<Path> = Arc::from(root);
+        let settings = git::Settings::load(&root, env)?;
+        let case_sensitive = settings.ignore_case
+            .map_or(pattern::CASE_SENSITIVE_BY_DEFAULT, |ignore_case| !ignore_case);
+
+        Repository::with_excludes(root, settings.exclude_files, case_sensitive)
+    }
+
+    /// Load whichever of the given files of repository-wide patterns exist, then walk the given
+    /// directory, loading every `.gitignore` file found and layering them on top.
+    fn with_excludes(root: Arc<Path>, exclude_paths: Vec<PathBuf>, case_sensitive: bool)
+        -> Result<Repository, error::Error> {
+        let mut repository = Repository {
+            excludes: vec![],
+            exclude_paths: vec![],
+            files: BTreeMap::new(),
+            stamps: vec![],
+            cache: RwLock::new(Cache::default()),
+            root,
+            case_sensitive
+        };
+
+        for path in exclude_paths {
+            let stamp = stamp(&path);
+            if stamp.is_some() && path.is_file() {
+                let mut file = file::File::load(&path, repository.root.clone())?;
+                file.set_case_sensitive(case_sensitive);
+                repository.excludes.push(file);
+            }
+            repository.stamps.push((path.clone(), stamp));
+            repository.exclude_paths.push(path);
+        }
+
+        let mut roots = vec![repository.root.to_path_buf()];
+        while let Some(dir) = roots.pop() {
+            repository.load(&dir)?;
+
+            for entry in fs::read_dir(&dir).map_err(|err| error::Error::io(err, &dir))? {
+                let entry = entry.map_err(|err| error::Error::io(err, &dir))?;
+                let path = entry.path();
+                if path.ends_with(".git") {
+                    continue;
+                }
+
+                // As in Git, symbolic links are never followed, even to directories, so they can
+                // neither loop forever nor pull in rules from outside the repository.
+                let directory = entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false);
+                if directory && !repository.file_is_excluded(&path, true) {
+                    roots.push(path);
+                }
+            }
+        }
+
+        Ok(repository)
+    }
+
+    /// Sets whether the patterns in every loaded file are matched case sensitively, as with Git's
+    /// `core.ignoreCase` configuration variable. Note that this does not change which
+    /// `.gitignore` files were discovered when the repository was loaded.
+    pub fn set_case_sensitive(&mut self, case_sensitive: bool) {
+        self.case_sensitive = case_sensitive;
+        for file in self.excludes.iter_mut().chain(self.files.values_mut()) {
+            file.set_case_sensitive(case_sensitive);
+        }
+        *self.cache.get_mut().unwrap_or_else(|err| err.into_inner()) = Cache::default();
+    }
+
+    /// Checks whether any ignore file has been changed, created or removed since the repository
+    /// was loaded, and if so loads the repository again from scratch and forgets every remembered
+    /// decision. Returns true if anything had changed.
+    ///
+    /// Only the places ignore files were looked for when the repository was loaded are checked:
+    /// the excludes files, and a `.gitignore` file in every directory which was not excluded. A
+    /// `.gitignore` file in a directory created since then is not noticed.
+    pub fn refresh(&mut self) -> Result<bool, error::Error> {
+        if self.stamps.iter().all(|(path, old)| stamp(path) == *old) {
+            return Ok(false);
+        }
+
+        let exclude_paths = self.exclude_paths.clone();
+        *self = Repository::with_excludes(self.root.clone(), exclude_paths, self.case_sensitive)?;
+        Ok(true)
+    }
+
+    /// Returns true if, after checking against all the patterns found in every applicable
+    /// `.gitignore` file, the given path or any of its parent directories is matched by any of
+    /// the globs (applying negated patterns as expected). Note this function also returns true if
+    /// the path does not exist, or lies outside of the repository.
+    ///
+    /// As in Git, nothing inside an excluded directory can be re-included, whether by a later
+    /// negated pattern or by a `.gitignore` file inside that directory.
+    ///
+    /// If the value for `path` is not absolute, it will assumed to be relative to the root of the
+    /// repository.
+    pub fn is_excluded(&self, path: &Path) -> Result<bool, error::Error> {
+        let abs_path = self.abs_path(path);
+        walk::is_excluded(&self.root, &abs_path, |path, directory| self.matches_path(path, directory))
+    }
+
+    /// Returns true if the given path, or any of its parent directories, would be excluded by the
+    /// patterns found in every applicable `.gitignore` file. The second argument, `is_dir`, is a
+    /// `bool` representing whether the given path should be treated as a directory; every parent
+    /// of the path is always treated as a directory.
+    ///
+    /// This never touches the filesystem, so it can be used to check paths that do not exist yet.
+    ///
+    /// If the value for `path` is not absolute, it will assumed to be relative to the root of the
+    /// repository.
+    pub fn matches_path(&self, path: &Path, is_dir: bool) -> bool {
+        let abs_path = self.abs_path(path);
+        if !self.is_inside(&abs_path) {
+            return false;
+        }
+
+        abs_path.parent().is_some_and(|dir| self.dir_is_excluded(dir)) ||
+            self.file_is_excluded(&abs_path, is_dir)
+    }
+
+    /// Returns the pattern which decided whether the given path is excluded, along with the file
+    /// and line it was written on, or `None` if no pattern matched the path or any of its parent
+    /// directories. This is the information `git check-ignore -v` reports. The second argument,
+    /// `is_dir`, is a `bool` representing whether the given path should be treated as a
+    /// directory.
+    ///
+    /// If a parent directory is excluded, the pattern which excluded it is returned. Otherwise, the
+    /// last pattern to match the path is returned, even if it is negated; use
+    /// `Match::is_excluded` to tell the two apart. Like `matches_path`, this never touches the
+    /// filesystem.
+    ///
+    /// If the value for `path` is not absolute, it will assumed to be relative to the root of the
+    /// repository.
+    pub fn explain(&self, path: &Path, is_dir: bool) -> Option<pattern::Match<'_>> {
+        let abs_path = self.abs_path(path);
+        walk::explain(&self.root, &abs_path, is_dir, |path, directory| self.last_match(path, directory))
+    }
+
+    /// Returns a list of files that are not excluded by the rules in any of the loaded
+    /// `.gitignore` files. It recurses through all subdirectories and returns everything that is
+    /// not ignored.
+    ///
+    /// This stops at the first error; use `walk` to carry on past errors, or to process a large
+    /// tree without collecting every path first.
+    pub fn included_files(&self) -> Result<Vec<PathBuf>, error::Error> {
+        self.walk().map(|entry| entry.map(walk::DirEntry::into_path)).collect()
+    }
+
+    /// Returns an iterator which lazily walks the repository, yielding everything that is not
+    /// excluded by the rules in any of the loaded `.gitignore` files. Excluded directories are not
+    /// descended into. An error reading any directory or entry is yielded in its place, and the
+    /// walk carries on with the rest of the tree.
+    ///
+    /// As in Git, symbolic links are not followed: a link to a directory is yielded like a file,
+    /// and nothing behind it is.
+    pub fn walk(&self) -> walk::Walk<'_> {
+        self.walk_builder().build()
+    }
+
+    /// Returns a walk over the same tree as `walk` which reads directories on several threads at
+    /// once, for very large repositories. Use `run` to start it.
+    pub fn walk_parallel(&self) -> walk::WalkParallel<'_> {
+        self.walk_builder().build_parallel()
+    }
+
+    /// Returns a builder for a walk over the same tree as `walk`, which can limit its depth,
+    /// whether it follows symbolic links, and what it yields.
+    pub fn walk_builder(&self) -> walk::WalkBuilder<'_> {
+        walk::WalkBuilder::new(&self.root, move |path, directory| {
+            let excluded = self.file_is_excluded(path, directory);
+
+            // A walk only reaches paths whose parent directories are not excluded, so this is the
+            // final decision for a directory, and can be remembered for later checks.
+            if directory {
+                self.remember_excluded(path, excluded);
+            }
+
+            excluded
+        })
+    }
+
+    /// Returns true if, after checking against all the patterns found in the `.gitignore` files
+    /// of every parent directory, the given path is matched any of the globs. The files are
+    /// applied from the root downwards, so that the deepest matching pattern wins, and all of
+    /// them take precedence over the repository-wide excludes files.
+    ///
+    /// Note very importantly that this method _does not_ check if the parent directories are
+    /// excluded. This is only for determining if the file itself matched any rules.
+    fn file_is_excluded(&self, path: &Path, directory: bool) -> bool {
+        path.parent().is_some_and(|dir| self.rules_for(dir).set.is_excluded(path, directory))
+    }
+
+    /// Returns the last pattern to match the given path, searching the files in order of
+    /// decreasing precedence, without checking the parent directories.
+    fn last_match(&self, path: &Path, directory: bool) -> Option<pattern::Match<'_>> {
+        let rules = self.rules_for(path.parent()?);
+        let (ref source, index) = rules.origins[rules.set.last_match(path, directory)?];
+
+        let file = match *source {
+            Source::Exclude(exclude) => &self.excludes[exclude],
+            Source::Gitignore(ref dir) => &self.files[dir]
+        };
+        Some(pattern::Match { pattern: &file.patterns()[index], path: path.to_path_buf() })
+    }
+
+    /// Returns true if the given absolute directory, or any of its parent directories, is excluded.
+    /// The decision for each directory is remembered.
+    fn dir_is_excluded(&self, dir: &Path) -> bool {
+        if !self.is_inside(dir) {
+            return false;
+        }
+
+        if let Some(&excluded) = self.cache().excluded.get(dir) {
+            return excluded;
+        }
+
+        let excluded = dir.parent().is_some_and(|parent| self.dir_is_excluded(parent)) ||
+            self.file_is_excluded(dir, true);
+        self.remember_excluded(dir, excluded);
+        excluded
+    }
+
+    /// Remember whether the given directory, or any directory above it, is excluded.
+    fn remember_excluded(&self, dir: &Path, excluded: bool) {
+        let mut cache = self.cache_mut();
+        cache.make_room();
+        cache.excluded.insert(dir.to_path_buf(), excluded);
+    }
+
+    /// Returns the rules for the paths directly inside the given absolute directory: every
+    /// pattern which could match one of them, from the excludes files and the `.gitignore` files
+    /// of the directory and its parents. The rules for each directory are remembered.
+    fn rules_for(&self, dir: &Path) -> Arc<Rules> {
+        if let Some(rules) = self.cache().rules.get(dir) {
+            return rules.clone();
+        }
+
+        // The files are gathered from the highest precedence down, then reversed so that later
+        // patterns take precedence, as in a single file.
+        let excludes = self.excludes.iter().enumerate().rev();
+        let mut files: Vec<(Source, &file::File)> = dir.ancestors()
+            .take_while(|dir| dir.starts_with(&self.root))
+            .filter_map(|dir| self.files.get_key_value(dir))
+            .map(|(dir, file)| (Source::Gitignore(dir.clone()), file))
+            .chain(excludes.map(|(index, file)| (Source::Exclude(index), file)))
+            .collect();
+        files.reverse();
+
+        let candidates: Vec<(Origin, &pattern::Pattern)> = files.iter()
+            .flat_map(|&(ref source, file)| {
+                file.patterns().iter()
+                    .enumerate()
+                    .filter(|&(_, pattern)| set::may_match_inside(pattern, dir))
+                    .map(move |(index, pattern)| ((source.clone(), index), pattern))
+            })
+            .collect();
+        let origins: Vec<Origin> = candidates.iter().map(|(origin, _)| origin.clone()).collect();
+
+        let mut cache = self.cache_mut();
+        cache.make_room();
+        let rules = match cache.shared.get(&origins) {
+            Some(rules) => rules.clone(),
+            None => {
+                let patterns = candidates.iter().map(|&(_, pattern)| pattern.clone()).collect();
+                let rules = Arc::new(Rules { set: set::PatternSet::new(patterns), origins: origins.clone() });
+                cache.shared.insert(origins, rules.clone());
+                rules
+            }
+        };
+        cache.rules.insert(dir.to_path_buf(), rules.clone());
+        rules
+    }
+
+    /// The decisions remembered about directories. A panic while they were being updated cannot
+    /// leave them wrong, only incomplete, so a poisoned lock is ignored.
+    fn cache(&self) -> RwLockReadGuard<'_, Cache> {
+        self.cache.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// The decisions remembered about directories, for updating.
+    fn cache_mut(&self) -> RwLockWriteGuard<'_, Cache> {
+        self.cache.write().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Returns true if the given absolute path lies inside the repository, and is not the root.
+    fn is_inside(&self, path: &Path) -> bool {
+        path != &*self.root && path.starts_with(&self.root)
+    }
+
+    /// Load the `.gitignore` file in the given directory, if there is one.
+    fn load(&mut self, dir: &Path) -> Result<(), error::Error> {
+        let gitignore_path = dir.join(".gitignore");
+        let stamp = stamp(&gitignore_path);
+        if stamp.is_some() && gitignore_path.is_file() {
+            let mut file = file::File::load(&gitignore_path, Arc::from(dir))?;
+            file.set_case_sensitive(self.case_sensitive);
+            self.files.insert(Arc::from(dir), file);
+        }
+        self.stamps.push((gitignore_path, stamp));
+
+        Ok(())
+    }
+
+    /// Given a path, make it absolute if relative by joining it to the repository root, otherwise
+    /// leave absolute as originally given.
+    fn abs_path(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() {
+            path.to_owned()
+        } else {
+            self.root.join(path)
+        }
+    }
+}
+
+/// Returns the stamp of the file at the given path, or `None` if there is nothing there.
+fn stamp(path: &Path) -> Option<Stamp> {
+    fs::metadata(path).ok().map(|metadata| (metadata.modified().ok(), metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::{Cache, Repository, CACHE_LIMIT};
+
+    use error::ErrorKind;
+
+    use git;
+
+    use testing::assert_owned;
+
+    use std::fs;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_nested_patterns_only_apply_below_their_directory() {
+        with_fake_repo(vec![(".gitignore", "*.log"), ("sub/.gitignore", "*.tmp")],
+                       vec!["a.tmp", "sub/b.tmp", "sub/c.log", "sub/deeper/d.tmp"], |root| {
+            let repository = Repository::new(root).unwrap();
+
+            assert!(!repository.is_excluded(Path::new("a.tmp")).unwrap());
+            assert!(repository.is_excluded(Path::new("sub/b.tmp")).unwrap());
+            assert!(repository.is_excluded(Path::new("sub/c.log")).unwrap());
+            assert!(repository.is_excluded(Path::new("sub/deeper/d.tmp")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_nested_anchored_patterns_are_relative_to_their_directory() {
+        with_fake_repo(vec![("sub/.gitignore", "/out")],
+                       vec!["out", "sub/out", "sub/deeper/out"], |root| {
+            let repository = Repository::new(root).unwrap();
+
+            assert!(!repository.is_excluded(Path::new("out")).unwrap());
+            assert!(repository.is_excluded(Path::new("sub/out")).unwrap());
+            assert!(!repository.is_excluded(Path::new("sub/deeper/out")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_nested_middle_slash_patterns_are_relative_to_their_directory() {
+        with_fake_repo(vec![("sub/.gitignore", "doc/frotz")],
+                       vec!["doc/frotz", "sub/doc/frotz", "sub/a/doc/frotz"], |root| {
+            let repository = Repository::new(root).unwrap();
+
+            assert!(!repository.is_excluded(Path::new("doc/frotz")).unwrap());
+            assert!(repository.is_excluded(Path::new("sub/doc/frotz")).unwrap());
+            assert!(!repository.is_excluded(Path::new("sub/a/doc/frotz")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_deeper_files_override_shallower_files() {
+        with_fake_repo(vec![(".gitignore", "*.log"), ("sub/.gitignore", "!keep.log")],
+                       vec!["keep.log", "sub/keep.log", "sub/other.log"], |root| {
+            let repository = Repository::new(root).unwrap();
+
+            assert!(repository.is_excluded(Path::new("keep.log")).unwrap());
+            assert!(!repository.is_excluded(Path::new("sub/keep.log")).unwrap());
+            assert!(repository.is_excluded(Path::new("sub/other.log")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_gitignore_in_excluded_directory_is_not_loaded() {
+        with_fake_repo(vec![(".gitignore", "build/"), ("build/.gitignore", "!*")],
+                       vec!["build/out"], |root| {
+            let repository = Repository::new(root).unwrap();
+
+            assert!(repository.is_excluded(Path::new("build/out")).unwrap());
+            assert!(!repository.files.contains_key(root.join("build").as_path()));
+        })
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_symbolic_links_are_not_followed_for_gitignore_files() {
+        use std::os::unix::fs::symlink;
+
+        with_fake_repo(vec![("repo/.gitignore", "*.log"), ("outside/.gitignore", "*.txt")],
+                       vec!["repo/sub/a.txt"], |root| {
+            let repo = root.join("repo");
+            symlink(".", repo.join("sub/l1")).unwrap();
+            symlink(".", repo.join("sub/l2")).unwrap();
+            symlink(root.join("outside"), repo.join("sub/out")).unwrap();
+
+            let repository = Repository::new(&repo).unwrap();
+            assert!(repository.files.len() == 1);
+            assert!(repository.is_excluded(Path::new("sub/b.log")).unwrap());
+            assert!(!repository.is_excluded(Path::new("sub/a.txt")).unwrap());
+            assert!(!repository.matches_path(Path::new("sub/out/c.txt"), false));
+        })
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_walk_does_not_follow_symbolic_links() {
+        use std::os::unix::fs::symlink;
+
+        with_fake_repo(vec![("repo/.gitignore", "*.log")], vec!["repo/a.txt", "outside/b.txt"], |root| {
+            let repo = root.join("repo");
+            symlink(root.join("outside"), repo.join("out")).unwrap();
+
+            let repository = Repository::new(&repo).unwrap();
+            let mut entries: Vec<_> = repository.walk().map(|entry| entry.unwrap()).map(|entry| {
+                (entry.path().strip_prefix(&repo).unwrap().to_path_buf(), entry.file_type().is_symlink())
+            }).collect();
+            entries.sort();
+            assert!(entries == vec![(PathBuf::from(".gitignore"), false), (PathBuf::from("a.txt"), false),
+                                    (PathBuf::from("out"), true)]);
+
+            let followed = repository.walk_builder().follow_links(true).build().map(|entry| entry.unwrap());
+            assert!(followed.filter(|entry| entry.path() == repo.join("out/b.txt")).count() == 1);
+        })
+    }
+
+    #[test]
+    fn test_open_loads_info_exclude_and_excludes_file() {
+        with_fake_repo(vec![(".git/info/exclude", "*.tmp\n!keep.log"),
+                            (".git/config", "[core]\n\texcludesFile = ~/global-ignore\n"),
+                            ("home/global-ignore", "*.log\n*.txt"),
+                            (".gitignore", "!a.txt")],
+                       vec!["a.tmp", "b.log", "keep.log", "a.txt", "b.txt"], |root| {
+            let env = git::Env { home: Some(root.join("home")), xdg_config_home: None };
+            let repository = Repository::open_with(root, &env).unwrap();
+
+            assert!(repository.is_excluded(Path::new("a.tmp")).unwrap());
+            assert!(repository.is_excluded(Path::new("b.log")).unwrap());
+            assert!(!repository.is_excluded(Path::new("keep.log")).unwrap());
+            assert!(!repository.is_excluded(Path::new("a.txt")).unwrap());
+            assert!(repository.is_excluded(Path::new("b.txt")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_open_follows_core_ignore_case() {
+        with_fake_repo(vec![(".git/config", "[core]\n\tignoreCase = true\n"), (".gitignore", "foo.o")],
+                       vec!["Foo.o"], |root| {
+            let env = git::Env { home: None, xdg_config_home: None };
+            let mut repository = Repository::open_with(root, &env).unwrap();
+            assert!(repository.is_excluded(Path::new("Foo.o")).unwrap());
+
+            repository.set_case_sensitive(true);
+            assert!(!repository.is_excluded(Path::new("Foo.o")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_open_falls_back_to_xdg_ignore() {
+        with_fake_repo(vec![("xdg/git/ignore", "*.log")], vec!["a.log", "b.tmp"], |root| {
+            fs::create_dir(root.join(".git")).unwrap();
+            let env = git::Env { home: None, xdg_config_home: Some(root.join("xdg")) };
+            let repository = Repository::open_with(root, &env).unwrap();
+
+            assert!(repository.is_excluded(Path::new("a.log")).unwrap());
+            assert!(!repository.is_excluded(Path::new("b.tmp")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_open_follows_git_file_to_worktree_common_dir() {
+        with_fake_repo(vec![("main/.git/info/exclude", "*.tmp"),
+                            ("main/.git/worktrees/wt/commondir", "../.."),
+                            ("wt/.git", "gitdir: ../main/.git/worktrees/wt\n")],
+                       vec!["wt/a.tmp", "wt/b.log"], |root| {
+            let env = git::Env { home: None, xdg_config_home: None };
+            let worktree = root.join("wt");
+            let repository = Repository::open_with(&worktree, &env).unwrap();
+
+            assert!(repository.is_excluded(Path::new("a.tmp")).unwrap());
+            assert!(!repository.is_excluded(Path::new("b.log")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_explain() {
+        with_fake_repo(vec![(".git/info/exclude", "*.tmp"),
+                            (".gitignore", "*.log\nbuild/"),
+                            ("sub/.gitignore", "!keep.log")],
+                       vec![], |root| {
+            let env = git::Env { home: None, xdg_config_home: None };
+            let repository = Repository::open_with(root, &env).unwrap();
+
+            let found = repository.explain(Path::new("sub/debug.log"), false).unwrap();
+            assert!(found.is_excluded());
+            assert!(found.source() == Some(root.join(".gitignore").as_path()));
+            assert!(found.line() == 1);
+
+            let found = repository.explain(Path::new("sub/keep.log"), false).unwrap();
+            assert!(!found.is_excluded());
+            assert!(found.source() == Some(root.join("sub/.gitignore").as_path()));
+
+            let found = repository.explain(Path::new("sub/a.tmp"), false).unwrap();
+            assert!(found.source() == Some(root.join(".git/info/exclude").as_path()));
+            assert!(found.text() == b"*.tmp");
+
+            let found = repository.explain(Path::new("build/sub/keep.log"), false).unwrap();
+            assert!(found.text() == b"build/");
+            assert!(found.path == root.join("build"));
+
+            assert!(repository.explain(Path::new("src/lib.rs"), false).is_none());
+        })
+    }
+
+    #[test]
+    fn test_open_reads_config_with_non_utf8_values() {
+        with_fake_repo(vec![("home/global-ignore", "*.log")], vec!["a.log", "b.tmp"], |root| {
+            fs::create_dir(root.join(".git")).unwrap();
+            let config = b"[user]\n\tname = Jos\xe9\n[core]\n\texcludesFile = ~/global-ignore\n";
+            fs::write(root.join(".git/config"), config).unwrap();
+
+            let env = git::Env { home: Some(root.join("home")), xdg_config_home: None };
+            let repository = Repository::open_with(root, &env).unwrap();
+            assert!(repository.is_excluded(Path::new("a.log")).unwrap());
+            assert!(!repository.is_excluded(Path::new("b.tmp")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_relative_root_is_rejected() {
+        let err = Repository::new(Path::new("wing")).unwrap_err();
+        assert!(err.kind() == ErrorKind::NotAbsolute);
+        assert!(Repository::open(Path::new("wing")).unwrap_err().kind() == ErrorKind::NotAbsolute);
+    }
+
+    #[test]
+    fn test_repository_is_owned() {
+        with_fake_repo(vec![(".gitignore", "*.log")], vec!["a.log"], |root| {
+            let repository = Repository::new(root).unwrap();
+            assert_owned(&repository);
+            assert!(repository.is_excluded(&root.join("a.log")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_included_files() {
+        with_fake_repo(vec![(".gitignore", "*.log"), ("sub/.gitignore", "*.tmp")],
+                       vec!["a.tmp", "b.log", "sub/c.tmp"], |root| {
+            let repository = Repository::new(root).unwrap();
+            let files: Vec<String> = repository.included_files().unwrap().iter().map(|path|
+                path.strip_prefix(root).unwrap().to_str().unwrap().to_string()
+            ).collect();
+
+            // We can't compare the vec directly, as the order can differ
+            // depending on underlying platform. Instead, let's break it
+            // apart into the respective assertions.
+            assert!(files.len() == 4);
+            assert!(files.contains(&".gitignore".to_string()));
+            assert!(files.contains(&"a.tmp".to_string()));
+            assert!(files.contains(&"sub".to_string()));
+            assert!(files.contains(&"sub/.gitignore".to_string()));
+        })
+    }
+
+    #[test]
+    fn test_nested_file_cannot_reinclude_inside_excluded_directory() {
+        for &(root_contents, excluded) in &[("build/", true), ("build/*", false)] {
+            with_fake_repo(vec![(".gitignore", root_contents), ("build/.gitignore", "!keep.txt")],
+                           vec!["build/keep.txt"], |root| {
+                let repository = Repository::new(root).unwrap();
+                let keep = Path::new("build/keep.txt");
+                let included = repository.included_files().unwrap();
+
+                assert!(repository.is_excluded(keep).unwrap() == excluded);
+                assert!(repository.matches_path(keep, false) == excluded);
+                assert!(repository.explain(keep, false).unwrap().is_excluded() == excluded);
+                assert!(included.contains(&root.join(keep)) != excluded);
+            })
+        }
+    }
+
+    #[test]
+    fn test_directory_decisions_are_remembered() {
+        with_fake_repo(vec![(".gitignore", "build/\n*.log"), ("sub/.gitignore", "*.tmp")],
+                       vec!["build/a/b.txt", "sub/deeper/c.tmp", "d.txt"], |root| {
+            let repository = Repository::new(root).unwrap();
+
+            assert!(repository.matches_path(Path::new("build/a/b.txt"), false));
+            assert!(repository.matches_path(Path::new("sub/deeper/c.tmp"), false));
+            assert!(!repository.matches_path(Path::new("sub/deeper/c.txt"), false));
+            {
+                let cache = repository.cache.read().unwrap();
+                assert!(cache.excluded[&root.join("build")]);
+                assert!(cache.excluded[&root.join("build/a")]);
+                assert!(!cache.excluded[&root.join("sub/deeper")]);
+
+                let texts: Vec<&[u8]> = cache.rules[&root.join("sub/deeper")].set.patterns().iter()
+                    .map(|pattern| pattern.text.as_slice())
+                    .collect();
+                assert!(texts == [b"build/".as_ref(), b"*.log", b"*.tmp"]);
+                assert!(Arc::ptr_eq(&cache.rules[&root.join("sub")], &cache.rules[&root.join("sub/deeper")]));
+            }
+
+            let repository = Repository::new(root).unwrap();
+            let included = repository.included_files().unwrap();
+            assert!(included.len() == 5);
+            assert!(!repository.cache.read().unwrap().excluded[&root.join("sub/deeper")]);
+            assert!(repository.cache.read().unwrap().excluded[&root.join("build")]);
+            assert!(repository.is_excluded(Path::new("build/a/b.txt")).unwrap());
+            assert!(!repository.is_excluded(Path::new("d.txt")).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_rules_only_hold_patterns_which_could_match() {
+        with_fake_repo(vec![(".gitignore", "/target/debug\n/src/*.o\n*.log\n/Makefile")], vec![], |root| {
+            let repository = Repository::new(root).unwrap();
+            let texts = |dir: &str| -> Vec<Vec<u8>> {
+                repository.rules_for(&root.join(dir)).set.patterns().iter()
+                    .map(|pattern| pattern.text.clone())
+                    .collect()
+            };
+
+            assert!(texts("") == [b"/src/*.o".to_vec(), b"*.log".to_vec(), b"/Makefile".to_vec()]);
+            assert!(texts("src") == [b"/src/*.o".to_vec(), b"*.log".to_vec()]);
+            assert!(texts("target") == [b"/target/debug".to_vec(), b"*.log".to_vec()]);
+            assert!(texts("target/debug") == [b"*.log".to_vec()]);
+
+            let found = repository.explain(Path::new("target/debug"), true).unwrap();
+            assert!(found.text() == b"/target/debug" && found.line() == 1);
+        })
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let mut cache = Cache::default();
+        for index in 0..CACHE_LIMIT {
+            cache.make_room();
+            cache.excluded.insert(PathBuf::from(index.to_string()), false);
+        }
+        assert!(cache.excluded.len() == CACHE_LIMIT);
+
+        cache.make_room();
+        assert!(cache.excluded.is_empty());
+    }
+
+    #[test]
+    fn test_refresh_forgets_remembered_rules() {
+        with_fake_repo(vec![(".gitignore", "*.log")], vec!["sub/a.log", "sub/a.tmp"], |root| {
+            let mut repository = Repository::new(root).unwrap();
+            assert!(repository.is_excluded(Path::new("sub/a.log")).unwrap());
+            assert!(repository.cache.read().unwrap().rules.contains_key(&root.join("sub")));
+            assert!(!repository.cache.read().unwrap().excluded[&root.join("sub")]);
+
+            write_to_file(&root.join(".gitignore"), "sub/\n");
+            assert!(!repository.is_excluded(Path::new("sub/a.tmp")).unwrap());
+            assert!(repository.refresh().unwrap());
+            {
+                let cache = repository.cache.read().unwrap();
+                assert!(cache.excluded.is_empty());
+                assert!(cache.rules.len() == 1 && !cache.rules.contains_key(&root.join("sub")));
+                assert!(cache.rules[&root.to_path_buf()].set.patterns()[0].text == b"sub/");
+            }
+
+            assert!(repository.is_excluded(Path::new("sub/a.tmp")).unwrap());
+            assert!(repository.cache.read().unwrap().excluded[&root.join("sub")]);
+        })
+    }
+
+    #[test]
+    fn test_refresh_picks_up_changed_files() {
+        with_fake_repo(vec![(".gitignore", "*.log")], vec!["a.log", "a.tmp", "sub/b.txt"], |root| {
+            let mut repository = Repository::new(root).unwrap();
+            assert!(repository.is_excluded(Path::new("a.log")).unwrap());
+            assert!(!repository.refresh().unwrap());
+
+            write_to_file(&root.join(".gitignore"), "*.tmp\n");
+            assert!(repository.refresh().unwrap());
+            assert!(!repository.is_excluded(Path::new("a.log")).unwrap());
+            assert!(repository.is_excluded(Path::new("a.tmp")).unwrap());
+
+            write_to_file(&root.join("sub/.gitignore"), "b.txt");
+            assert!(!repository.is_excluded(Path::new("sub/b.txt")).unwrap());
+            assert!(repository.refresh().unwrap());
+            assert!(repository.is_excluded(Path::new("sub/b.txt")).unwrap());
+
+            fs::remove_file(root.join("sub/.gitignore")).unwrap();
+            assert!(repository.refresh().unwrap());
+            assert!(!repository.is_excluded(Path::new("sub/b.txt")).unwrap());
+            assert!(!repository.refresh().unwrap());
+        })
+    }
+
+    fn with_fake_repo<F>(ignores: Vec<(&str, &str)>, files: Vec<&str>, callback: F)
+        where F: Fn(&Path) {
+        let dir = tempdir::TempDir::new("gitignore_tests").unwrap();
+
+        for (file, contents) in ignores.into_iter().chain(files.into_iter().map(|file| (file, ""))) {
+            let path = dir.path().join(file);
+            path.parent().map(fs::create_dir_all);
+            write_to_file(&path, contents);
+        }
+
+        callback(dir.path());
+        dir.close().unwrap();
+    }
+
+    fn write_to_file(path: &Path, contents: &str) {
+        let mut file = fs::File::create(path).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+    }
+}
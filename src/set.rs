@@ -0,0 +1,290 @@
+use pattern;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The indices of the patterns in a bucket, in ascending order, keyed by what they must match.
+type Buckets = HashMap<Vec<u8>, Vec<usize>>;
+
+/// A list of patterns compiled so that a path can be checked against all of them at once, without
+/// trying every pattern in turn. This makes checking paths against `.gitignore` files with
+/// hundreds of patterns much quicker.
+///
+/// Patterns are sorted into buckets by what any path they match must contain: a literal file
+/// name, such as `Cargo.lock`; a literal extension, such as `*.o`; or a literal first directory,
+/// such as `/target/debug`. Only the patterns in the buckets a path falls into, along with any
+/// patterns which fit no bucket, are tried against it, latest first.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+///
+/// let root = Path::new("/src");
+/// let patterns = ["*.o", "!keep.o", "/target/", "*~"].iter()
+///     .map(|glob| gitignore::Pattern::new(glob, root).unwrap())
+///     .collect();
+/// let set = gitignore::PatternSet::new(patterns);
+///
+/// assert!(set.last_match(Path::new("src/main.o"), false) == Some(0));
+/// assert!(set.last_match(Path::new("keep.o"), false) == Some(1));
+/// assert!(!set.is_excluded(Path::new("keep.o"), false));
+/// assert!(set.is_excluded(Path::new("target"), true));
+/// assert!(set.last_match(Path::new("src/target"), true).is_none());
+/// ```
+#[derive(Debug)]
+pub struct PatternSet {
+    patterns: Vec<pattern::Pattern>,
+    /// Unanchored patterns with no wildcards, by the file name they match.
+    names: Buckets,
+    /// Unanchored patterns of the form `*.ext`, by the extension they match.
+    extensions: Buckets,
+    /// Anchored patterns whose first component has no wildcards, by that component, grouped by
+    /// the root they are anchored to.
+    prefixes: Vec<(Arc<Path>, Buckets)>,
+    /// Patterns which fit in no other bucket, and so must be tried against every path.
+    globs: Vec<usize>
+}
+
+/// Which bucket of a `PatternSet` a pattern is sorted into.
+enum Bucket {
+    Name(Vec<u8>),
+    Extension(Vec<u8>),
+    Prefix(Vec<u8>),
+    Glob
+}
+
+impl PatternSet {
+    /// Compile the given patterns into a set. Their order is kept, so later patterns take
+    /// precedence over earlier ones, just as in a `.gitignore` file.
+    pub fn new(patterns: Vec<pattern::Pattern>) -> PatternSet {
+        let mut set = PatternSet {
+            patterns: vec![],
+            names: HashMap::new(),
+            extensions: HashMap::new(),
+            prefixes: vec![],
+            globs: vec![]
+        };
+
+        for (index, pattern) in patterns.iter().enumerate() {
+            match bucket(pattern) {
+                Bucket::Name(name) => set.names.entry(name).or_default().push(index),
+                Bucket::Extension(extension) => set.extensions.entry(extension).or_default().push(index),
+                Bucket::Prefix(prefix) => {
+                    let position = set.prefixes.iter().position(|(root, _)| *root == pattern.root);
+                    let position = position.unwrap_or_else(|| {
+                        set.prefixes.push((pattern.root.clone(), HashMap::new()));
+                        set.prefixes.len() - 1
+                    });
+                    set.prefixes[position].1.entry(prefix).or_default().push(index);
+                },
+                Bucket::Glob => set.globs.push(index)
+            }
+        }
+
+        set.patterns = patterns;
+        set
+    }
+
+    /// The patterns in the set, in the order they were given.
+    pub fn patterns(&self) -> &[pattern::Pattern] {
+        &self.patterns
+    }
+
+    /// Sets whether every pattern in the set is matched case sensitively.
+    pub fn set_case_sensitive(&mut self, case_sensitive: bool) {
+        // The buckets are keyed without regard to case, so they do not need to be rebuilt.
+        for pattern in &mut self.patterns {
+            pattern.case_sensitive = case_sensitive;
+        }
+    }
+
+    /// Returns the index of the last pattern in the set to match the given path, whether or not
+    /// it is negated, or `None` if none of them do. The second argument, `directory`, is a `bool`
+    /// representing whether the given path is a directory. As with `Pattern::matches`, the
+    /// parent directories of the path are not checked.
+    ///
+    /// If the value for `path` is not absolute, it will be assumed to be relative to the root of
+    /// the patterns.
+    pub fn last_match(&self, path: &Path, directory: bool) -> Option<usize> {
+        let mut candidates: Vec<&[usize]> = vec![&self.globs];
+
+        if let Some(name) = path.file_name() {
+            let name = pattern::path_bytes(Path::new(name)).to_ascii_lowercase();
+            candidates.extend(self.names.get(&name).map(Vec::as_slice));
+
+            let dots = name.iter().enumerate().filter(|&(_, &byte)| byte == b'.');
+            for (dot, _) in dots {
+                candidates.extend(self.extensions.get(&name[dot + 1..]).map(Vec::as_slice));
+            }
+        }
+
+        for (root, prefixes) in &self.prefixes {
+            let abs_path = root.join(path);
+            let first = abs_path.strip_prefix(root).ok().and_then(|relative| relative.components().next());
+            if let Some(first) = first {
+                let first = pattern::path_bytes(Path::new(first.as_os_str())).to_ascii_lowercase();
+                candidates.extend(prefixes.get(&first).map(Vec::as_slice));
+            }
+        }
+
+        // Each list of candidates is in ascending order, so repeatedly take the latest of their
+        // last elements to try the candidates from latest to earliest.
+        loop {
+            let (list, &index) = candidates.iter()
+                .enumerate()
+                .filter_map(|(list, candidates)| candidates.last().map(|index| (list, index)))
+                .max_by_key(|&(_, index)| index)?;
+
+            if self.patterns[index].matches(path, directory) {
+                return Some(index);
+            }
+
+            let remaining = candidates[list].len() - 1;
+            candidates[list] = &candidates[list][..remaining];
+        }
+    }
+
+    /// Returns true if the last pattern in the set to match the given path is not negated, and so
+    /// the path would be excluded if the set were a `.gitignore` file. As with `last_match`, the
+    /// parent directories of the path are not checked.
+    pub fn is_excluded(&self, path: &Path, directory: bool) -> bool {
+        self.last_match(path, directory).is_some_and(|index| !self.patterns[index].negation)
+    }
+}
+
+/// Decide which bucket the given pattern belongs in. Keys are lowercased, so that patterns which
+/// are matched case insensitively are found no matter the case of the path.
+fn bucket(pattern: &pattern::Pattern) -> Bucket {
+    let glob = &pattern.pattern;
+
+    if pattern.anchored {
+        let first = glob.split(|&byte| byte == b'/').next().unwrap_or(&[]);
+        if !first.is_empty() && is_literal(first) {
+            Bucket::Prefix(first.to_ascii_lowercase())
+        } else {
+            Bucket::Glob
+        }
+    } else if is_literal(glob) {
+        Bucket::Name(glob.to_ascii_lowercase())
+    } else if glob.starts_with(b"*.") && is_literal(&glob[2..]) {
+        Bucket::Extension(glob[2..].to_ascii_lowercase())
+    } else {
+        Bucket::Glob
+    }
+}
+
+/// Returns false if the given pattern can never match anything directly inside the given absolute
+/// directory, so it need not be tried against the paths there. This errs on the side of true, so
+/// true does not mean anything will match.
+///
+/// Only anchored patterns can be ruled out: each literal directory they start with must be the
+/// same as the directory at that depth, and a pattern with no `*` to span directories can only match
+/// a path with as many components as it has.
+pub(crate) fn may_match_inside(pattern: &pattern::Pattern, dir: &Path) -> bool {
+    if !pattern.anchored {
+        return true;
+    }
+
+    let relative = match dir.strip_prefix(&pattern.root) {
+        Ok(relative) => pattern::path_bytes(relative),
+        Err(_) => return false
+    };
+    let dirs: Vec<&[u8]> = relative.split(|&byte| byte == b'/').filter(|dir| !dir.is_empty()).collect();
+    let components: Vec<&[u8]> = pattern.pattern.split(|&byte| byte == b'/').collect();
+
+    let leading = components[..components.len() - 1].iter().take_while(|component| is_literal(component));
+    for (component, dir) in leading.zip(&dirs) {
+        // Only ASCII letters are ever matched regardless of case.
+        if !component.eq_ignore_ascii_case(dir) {
+            return false;
+        }
+    }
+
+    let spans_dirs = pattern.pattern.iter().any(|&byte| matches!(byte, b'*' | b'[' | b'\\'));
+    spans_dirs || components.len() == dirs.len() + 1
+}
+
+/// Whether the given glob contains no wildcards or escapes, and so only matches itself.
+fn is_literal(glob: &[u8]) -> bool {
+    !glob.iter().any(|&byte| matches!(byte, b'*' | b'?' | b'[' | b'\\'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{may_match_inside, PatternSet};
+
+    use pattern::Pattern;
+
+    use std::path::Path;
+
+    fn set(globs: &[&str], case_sensitive: bool) -> PatternSet {
+        let patterns = globs.iter().map(|glob| Pattern::new(glob, Path::new("/wing")).unwrap()).collect();
+        let mut set = PatternSet::new(patterns);
+        set.set_case_sensitive(case_sensitive);
+        set
+    }
+
+    #[test]
+    fn test_buckets() {
+        let globs = ["Cargo.lock", "*.o", "*.tar.gz", "/target/debug", "docs/*.html", "*~", "**/tmp", "\\!x"];
+        let set = set(&globs, true);
+
+        assert!(set.names.len() == 1 && set.names[b"cargo.lock".as_ref()] == vec![0]);
+        assert!(set.extensions.len() == 2 && set.extensions[b"tar.gz".as_ref()] == vec![2]);
+        assert!(set.prefixes.len() == 1 && set.prefixes[0].1.len() == 2);
+        assert!(set.globs == vec![5, 6, 7]);
+    }
+
+    #[test]
+    fn test_last_match_agrees_with_every_pattern() {
+        let globs = ["*.o", "!keep.o", "build", "/target/", "!/target/keep", "docs/**/*.html", "*.TAR.gz",
+                     "*~", "**/tmp", "/Makefile", "!*.html", "foo/", "a/b", "[ab].c", "*", "!*.*"];
+        let paths = ["x.o", "src/keep.o", "build", "src/build", "target", "target/keep", "src/target",
+                     "docs/a/b.html", "a.tar.gz", "A.TAR.GZ", "b.gz", "file~", "a/tmp", "Makefile",
+                     "src/Makefile", "foo", "a/b", "c/a/b", "a.c", "c.c", "plain", ".o", "x.", "sub/x.O"];
+
+        for &case_sensitive in &[true, false] {
+            for count in 1..=globs.len() {
+                let set = set(&globs[..count], case_sensitive);
+                for &path in &paths {
+                    for &directory in &[false, true] {
+                        let path = Path::new(path);
+                        let mut patterns = set.patterns().iter();
+                        let expected = patterns.rposition(|pattern| pattern.matches(path, directory));
+                        assert!(set.last_match(path, directory) == expected);
+                        assert!(set.is_excluded(path, directory) ==
+                                expected.is_some_and(|index| !set.patterns()[index].negation));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_may_match_inside_never_rules_out_a_match() {
+        let globs = ["/target/debug", "/src/*.o", "/Makefile", "a/b", "/a/**/b", "/?/c", "/[ab]/c", "/A/B",
+                     "*.o", "/a\\/b", "docs/"];
+        let paths = ["target/debug", "target", "src/x.o", "src/sub/x.o", "Makefile", "src/Makefile", "a/b",
+                     "a/x/y/b", "a/c", "b/c", "a/B", "x.o", "docs", "a/docs"];
+
+        for &glob in &globs {
+            let pattern = Pattern::new(glob, Path::new("/wing")).unwrap();
+            for &path in &paths {
+                let abs_path = Path::new("/wing").join(path);
+                if pattern.matches(&abs_path, true) || pattern.matches(&abs_path, false) {
+                    assert!(may_match_inside(&pattern, abs_path.parent().unwrap()));
+                }
+            }
+        }
+
+        let pattern = |glob| Pattern::new(glob, Path::new("/wing")).unwrap();
+        assert!(!may_match_inside(&pattern("/target/debug"), Path::new("/wing")));
+        assert!(!may_match_inside(&pattern("/target/debug"), Path::new("/wing/src")));
+        assert!(!may_match_inside(&pattern("/Makefile"), Path::new("/wing/src")));
+        assert!(!may_match_inside(&pattern("/src/*.o"), Path::new("/wing/lib")));
+        assert!(may_match_inside(&pattern("*.o"), Path::new("/wing/src")));
+        assert!(may_match_inside(&pattern("/a/**/b"), Path::new("/wing/a/x/y")));
+    }
+}
@@ -0,0 +1,4 @@
+//! Helpers shared by the tests of several modules.
+
+/// Fails to compile unless the given value owns all of its data and can be shared between threads.
+pub fn assert_owned<T: Send + Sync + 'static>(_: &T) {}
@@ -0,0 +1,932 @@
+use error;
+use pattern;
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+/// Returns true if the given absolute path is excluded, given a function that can match a path
+/// and its ancestors against a set of patterns. Paths outside of `root`, the root itself, paths
+/// inside a `.git` directory and paths that do not exist are all considered excluded, as they
+/// would never be returned by `included_files`.
+pub(crate) fn is_excluded<F>(root: &Path, path: &Path, matches_path: F) -> Result<bool, error::Error>
+    where F: Fn(&Path, bool) -> bool {
+    let relative = match path.strip_prefix(root) {
+        Ok(relative) if relative.components().next().is_some() => relative,
+        _ => return Ok(true)
+    };
+
+    if relative.components().any(|component| component.as_os_str() == ".git") {
+        return Ok(true);
+    }
+
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(matches_path(path, metadata.is_dir())),
+        Err(_) => Ok(true)
+    }
+}
+
+/// Returns true if the given absolute path, or any of its ancestors below `root`, are excluded
+/// according to the given function. Every ancestor is treated as a directory; the path itself is
+/// treated as a directory only if `is_dir` is true. Paths outside of `root` are never excluded.
+pub(crate) fn any_ancestor<F>(root: &Path, path: &Path, is_dir: bool, mut is_excluded: F) -> bool
+    where F: FnMut(&Path, bool) -> bool {
+    let relative = match path.strip_prefix(root) {
+        Ok(relative) => relative,
+        Err(_) => return false
+    };
+
+    let mut current = root.to_path_buf();
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        current.push(component);
+
+        let directory = is_dir || components.peek().is_some();
+        if is_excluded(&current, directory) {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Returns the pattern which decided whether the given absolute path is excluded, given a function
+/// that finds the last pattern to match a path. If any ancestor below `root` is excluded, the
+/// pattern which excluded the shallowest such ancestor is returned, as nothing inside it can be
+/// re-included; otherwise the last pattern to match the path itself, which may be negated, is
+/// returned. Paths outside of `root` are never matched.
+pub(crate) fn explain<'a, F>(root: &Path, path: &Path, is_dir: bool, mut last_match: F)
+    -> Option<pattern::Match<'a>>
+    where F: FnMut(&Path, bool) -> Option<pattern::Match<'a>> {
+    let relative = path.strip_prefix(root).ok()?;
+
+    let mut current = root.to_path_buf();
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        current.push(component);
+
+        let ancestor = components.peek().is_some();
+        match last_match(&current, is_dir || ancestor) {
+            Some(found) if !ancestor || found.is_excluded() => return Some(found),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Decides whether a path found during a walk is excluded, given whether it is a directory.
+type ExcludeFn<'a> = dyn Fn(&Path, bool) -> bool + Send + Sync + 'a;
+
+/// What a walk yields for each path it finds: the entry, or the error which stopped it being read.
+type WalkResult = Result<DirEntry, error::Error>;
+
+/// Identifies a directory, so that symbolic links which lead back to one of their own ancestors
+/// can be detected.
+#[cfg(unix)]
+type DirId = (u64, u64);
+
+#[cfg(unix)]
+fn dir_id(_: &Path, metadata: &fs::Metadata) -> DirId {
+    (metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+#[cfg(not(unix))]
+fn dir_id(path: &Path, _: &fs::Metadata) -> DirId {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The device a file lives on, where the platform can tell us.
+#[cfg(unix)]
+fn device(metadata: &fs::Metadata) -> Option<u64> {
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_: &fs::Metadata) -> Option<u64> {
+    None
+}
+
+/// A path found while walking a directory tree with `File::walk` or `Repository::walk`.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    path: PathBuf,
+    file_type: fs::FileType,
+    depth: usize
+}
+
+impl DirEntry {
+    /// The full path of the entry.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Consume the entry, returning its full path.
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    /// The final component of the path of the entry.
+    pub fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or_else(|| self.path.as_os_str())
+    }
+
+    /// The type of the entry. If the walk follows symbolic links, which it does not by default,
+    /// this is the type of whatever the link points to.
+    pub fn file_type(&self) -> fs::FileType {
+        self.file_type
+    }
+
+    /// Whether the entry is a directory, or a symbolic link to one which the walk followed.
+    pub fn is_dir(&self) -> bool {
+        self.file_type.is_dir()
+    }
+
+    /// How deep the entry is below the root of the walk. Entries directly inside the root have a
+    /// depth of 1.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+/// Configures a walk over a directory tree before it starts. It is created by `File::walk_builder`
+/// or `Repository::walk_builder`; `build` turns it into a `Walk` and `build_parallel` into a
+/// `WalkParallel`.
+///
+/// By default everything that is not excluded is walked, symbolic links are not followed, hidden
+/// files are included and the walk crosses onto other file systems. Whatever the options, `.git`
+/// directories are always skipped, and excluded directories are never descended into.
+pub struct WalkBuilder<'a> {
+    root: PathBuf,
+    options: Options,
+    is_excluded: Box<ExcludeFn<'a>>
+}
+
+#[derive(Clone, Debug)]
+struct Options {
+    min_depth: usize,
+    max_depth: Option<usize>,
+    follow_links: bool,
+    skip_hidden: bool,
+    same_file_system: bool,
+    files: bool,
+    dirs: bool,
+    ignored: bool
+}
+
+impl<'a> WalkBuilder<'a> {
+    /// Walk everything below `root` for which `is_excluded` returns false.
+    pub(crate) fn new<F>(root: &Path, is_excluded: F) -> WalkBuilder<'a>
+        where F: Fn(&Path, bool) -> bool + Send + Sync + 'a {
+        let options = Options {
+            min_depth: 0,
+            max_depth: None,
+            follow_links: false,
+            skip_hidden: false,
+            same_file_system: false,
+            files: true,
+            dirs: true,
+            ignored: false
+        };
+        WalkBuilder { root: root.to_path_buf(), options, is_excluded: Box::new(is_excluded) }
+    }
+
+    /// Only yield entries at least this deep. Directories above it are still descended into.
+    /// Entries directly inside the root have a depth of 1. Defaults to 0.
+    pub fn min_depth(mut self, depth: usize) -> WalkBuilder<'a> {
+        self.options.min_depth = depth;
+        self
+    }
+
+    /// Do not yield or descend into anything deeper than this, if given. `Some(1)` yields only
+    /// what is directly inside the root. Defaults to `None`.
+    pub fn max_depth(mut self, depth: Option<usize>) -> WalkBuilder<'a> {
+        self.options.max_depth = depth;
+        self
+    }
+
+    /// Whether to follow symbolic links. A link to a directory which is followed is walked like
+    /// any other directory, unless it leads back to one of its own ancestors, in which case an
+    /// error of kind `FilesystemLoop` is yielded instead. A link which is not followed is treated
+    /// as a file, as Git does. Defaults to false, as Git never looks beyond a link, and so neither
+    /// do the rules loaded for a repository.
+    pub fn follow_links(mut self, follow: bool) -> WalkBuilder<'a> {
+        self.options.follow_links = follow;
+        self
+    }
+
+    /// Whether to skip hidden files and directories, whose names begin with a dot. Defaults to
+    /// false.
+    pub fn skip_hidden(mut self, skip: bool) -> WalkBuilder<'a> {
+        self.options.skip_hidden = skip;
+        self
+    }
+
+    /// Whether to stay on the file system the root is on. Directories on other file systems, such
+    /// as mount points, are still yielded but not descended into. This has no effect on platforms
+    /// other than Unix. Defaults to false.
+    pub fn same_file_system(mut self, same: bool) -> WalkBuilder<'a> {
+        self.options.same_file_system = same;
+        self
+    }
+
+    /// Whether to yield anything which is not a directory. Defaults to true.
+    pub fn files(mut self, files: bool) -> WalkBuilder<'a> {
+        self.options.files = files;
+        self
+    }
+
+    /// Whether to yield directories. They are descended into either way. Defaults to true.
+    pub fn dirs(mut self, dirs: bool) -> WalkBuilder<'a> {
+        self.options.dirs = dirs;
+        self
+    }
+
+    /// Whether to yield what is excluded instead of what is not. Excluded directories are still
+    /// not descended into, so they are yielded but nothing inside them is. Defaults to false.
+    pub fn ignored(mut self, ignored: bool) -> WalkBuilder<'a> {
+        self.options.ignored = ignored;
+        self
+    }
+
+    /// Build an iterator which walks the tree lazily on the current thread.
+    pub fn build(self) -> Walk<'a> {
+        let (walker, root) = self.into_walker();
+        Walk { walker, pending: vec![root], current: None }
+    }
+
+    /// Build a walk which reads directories on several threads at once.
+    pub fn build_parallel(self) -> WalkParallel<'a> {
+        let (walker, root) = self.into_walker();
+        let threads = thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1);
+        WalkParallel { walker, root, threads, sorted: false }
+    }
+
+    fn into_walker(self) -> (Walker<'a>, Dir) {
+        let metadata = if self.options.follow_links || self.options.same_file_system {
+            fs::metadata(&self.root).ok()
+        } else {
+            None
+        };
+
+        let device = metadata.as_ref().filter(|_| self.options.same_file_system).and_then(device);
+        let ancestors = metadata.as_ref().filter(|_| self.options.follow_links).map(|metadata| {
+            Arc::new(Ancestor { id: dir_id(&self.root, metadata), parent: None })
+        });
+
+        let walker = Walker { options: self.options, device, is_excluded: self.is_excluded };
+        (walker, Dir { path: self.root, depth: 0, ancestors })
+    }
+}
+
+impl<'a> fmt::Debug for WalkBuilder<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("WalkBuilder")
+            .field("root", &self.root)
+            .field("options", &self.options)
+            .finish()
+    }
+}
+
+/// A directory waiting to be read, along with the directories above it if links are followed.
+#[derive(Debug)]
+struct Dir {
+    path: PathBuf,
+    depth: usize,
+    ancestors: Option<Arc<Ancestor>>
+}
+
+#[derive(Debug)]
+struct Ancestor {
+    id: DirId,
+    parent: Option<Arc<Ancestor>>
+}
+
+/// The decisions shared by every kind of walk about what to yield and what to descend into.
+struct Walker<'a> {
+    options: Options,
+    /// The device the root is on, if the walk must stay on it.
+    device: Option<u64>,
+    is_excluded: Box<ExcludeFn<'a>>
+}
+
+impl<'a> Walker<'a> {
+    /// Decide what to do with a path found in the given directory: what, if anything, to yield
+    /// for it, and whether it is a directory to read in turn. Paths whose type cannot be
+    /// determined, such as broken symbolic links, are skipped.
+    fn visit(&self, parent: &Dir, path: PathBuf) -> (Option<WalkResult>, Option<Dir>) {
+        // The name is checked as bytes, so that hidden files are skipped whatever their encoding.
+        let skipped = match path.file_name().map(OsStr::as_encoded_bytes) {
+            Some(b".git") => true,
+            Some(name) => self.options.skip_hidden && name.starts_with(b"."),
+            None => false
+        };
+        if skipped {
+            return (None, None);
+        }
+
+        let metadata = if self.options.follow_links {
+            fs::metadata(&path)
+        } else {
+            fs::symlink_metadata(&path)
+        };
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(_) => return (None, None)
+        };
+
+        let file_type = metadata.file_type();
+        let excluded = (self.is_excluded)(&path, file_type.is_dir());
+        if excluded && !self.options.ignored {
+            return (None, None);
+        }
+
+        let depth = parent.depth + 1;
+        let within_depth = self.options.max_depth.is_none_or(|max| depth <= max);
+        let descend = !excluded
+            && file_type.is_dir()
+            && self.options.max_depth.is_none_or(|max| depth < max)
+            && (self.device.is_none() || device(&metadata) == self.device);
+
+        let dir = if descend {
+            let ancestors = match parent.ancestors {
+                Some(ref parent) => {
+                    let id = dir_id(&path, &metadata);
+                    let mut ancestors = iter::successors(Some(parent), |ancestor| ancestor.parent.as_ref());
+                    if ancestors.any(|ancestor| ancestor.id == id) {
+                        return (Some(Err(error::Error::filesystem_loop(&path))), None);
+                    }
+                    Some(Arc::new(Ancestor { id, parent: Some(parent.clone()) }))
+                },
+                None => None
+            };
+            Some(Dir { path: path.clone(), depth, ancestors })
+        } else {
+            None
+        };
+
+        let wanted = excluded == self.options.ignored
+            && if file_type.is_dir() { self.options.dirs } else { self.options.files };
+        let entry = if wanted && within_depth && depth >= self.options.min_depth {
+            Some(Ok(DirEntry { path, file_type, depth }))
+        } else {
+            None
+        };
+
+        (entry, dir)
+    }
+}
+
+/// An iterator which lazily walks a directory tree, yielding everything that is not excluded. It
+/// is created by `File::walk` or `Repository::walk`, or by a `WalkBuilder`.
+///
+/// Directories which are excluded are not descended into, and `.git` directories are always
+/// skipped. If a directory or one of its entries cannot be read, an error is yielded for it and
+/// the walk carries on with the rest of the tree.
+pub struct Walk<'a> {
+    walker: Walker<'a>,
+    /// Directories which have been found but not yet read.
+    pending: Vec<Dir>,
+    /// The directory currently being read.
+    current: Option<(fs::ReadDir, Dir)>
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = WalkResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (entries, dir) = match self.current {
+                Some((ref mut entries, ref dir)) => (entries, dir),
+                None => {
+                    let dir = self.pending.pop()?;
+                    match fs::read_dir(&dir.path) {
+                        Ok(entries) => self.current = Some((entries, dir)),
+                        Err(err) => return Some(Err(error::Error::io(err, &dir.path)))
+                    }
+                    continue;
+                }
+            };
+
+            let path = match entries.next() {
+                Some(Ok(entry)) => entry.path(),
+                Some(Err(err)) => return Some(Err(error::Error::io(err, &dir.path))),
+                None => {
+                    self.current = None;
+                    continue;
+                }
+            };
+
+            let (entry, found) = self.walker.visit(dir, path);
+            if let Some(found) = found {
+                self.pending.push(found);
+            }
+            if entry.is_some() {
+                return entry;
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Walk<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("Walk")
+            .field("options", &self.walker.options)
+            .field("pending", &self.pending)
+            .field("current", &self.current.as_ref().map(|(_, dir)| dir))
+            .finish()
+    }
+}
+
+/// A walk over a directory tree which reads directories on several threads at once. It is created
+/// by `File::walk_parallel` or `Repository::walk_parallel`, or by a `WalkBuilder`, and makes the
+/// same decisions about what to yield as `Walk`.
+pub struct WalkParallel<'a> {
+    walker: Walker<'a>,
+    root: Dir,
+    threads: usize,
+    sorted: bool
+}
+
+/// The directories waiting to be read by a parallel walk. Each thread has its own deque: it takes
+/// directories from the back of its own, so that it walks depth first, and when that is empty it
+/// steals from the front of another thread's. Threads only contend for the same lock when they
+/// run out of work.
+struct Queue {
+    deques: Vec<Mutex<VecDeque<Dir>>>,
+    /// How many directories have been found but not yet completely read. The walk is finished
+    /// once this reaches zero.
+    outstanding: AtomicUsize,
+    /// Set once there is no one left to deliver results to, so the walk should stop early.
+    stopped: AtomicBool,
+    /// How many threads are waiting for another to find more work.
+    sleepers: AtomicUsize,
+    sleep: Mutex<()>,
+    wake: Condvar
+}
+
+impl Queue {
+    /// A queue for the given number of threads, holding just the root.
+    fn new(root: Dir, threads: usize) -> Queue {
+        let deques: Vec<_> = (0..threads).map(|_| Mutex::new(VecDeque::new())).collect();
+        lock(&deques[0]).push_back(root);
+
+        Queue {
+            deques,
+            outstanding: AtomicUsize::new(1),
+            stopped: AtomicBool::new(false),
+            sleepers: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wake: Condvar::new()
+        }
+    }
+
+    /// Add a directory found by the given thread, waking a thread which is waiting for work.
+    fn push(&self, thread: usize, dir: Dir) {
+        self.outstanding.fetch_add(1, SeqCst);
+        lock(&self.deques[thread]).push_back(dir);
+
+        if self.sleepers.load(SeqCst) > 0 {
+            let _sleep = lock(&self.sleep);
+            self.wake.notify_one();
+        }
+    }
+
+    /// Take the next directory for the given thread to read, waiting until one is found if every
+    /// deque is empty but other threads are still reading. Returns `None` once the walk is over.
+    fn pop(&self, thread: usize) -> Option<Dir> {
+        loop {
+            if self.stopped.load(SeqCst) {
+                return None;
+            }
+            if let Some(dir) = self.steal(thread) {
+                return Some(dir);
+            }
+            if self.outstanding.load(SeqCst) == 0 {
+                return None;
+            }
+
+            // Check again while holding the lock, as `push`, `done` and `stop` take it before
+            // waking anyone, so that a wakeup cannot be missed between the check and the wait.
+            let sleep = lock(&self.sleep);
+            self.sleepers.fetch_add(1, SeqCst);
+            let idle = !self.stopped.load(SeqCst)
+                && self.outstanding.load(SeqCst) != 0
+                && self.deques.iter().all(|deque| lock(deque).is_empty());
+            if idle {
+                drop(self.wake.wait(sleep).unwrap_or_else(|err| err.into_inner()));
+            }
+            self.sleepers.fetch_sub(1, SeqCst);
+        }
+    }
+
+    /// Take a directory from the back of the given thread's own deque, or failing that from the
+    /// front of any other thread's.
+    fn steal(&self, thread: usize) -> Option<Dir> {
+        if let Some(dir) = lock(&self.deques[thread]).pop_back() {
+            return Some(dir);
+        }
+
+        let count = self.deques.len();
+        (1..count).find_map(|offset| lock(&self.deques[(thread + offset) % count]).pop_front())
+    }
+
+    /// Record that a directory taken with `pop` has been completely read.
+    fn done(&self) {
+        if self.outstanding.fetch_sub(1, SeqCst) == 1 {
+            self.wake_all();
+        }
+    }
+
+    /// Stop the walk, leaving whatever has not yet been read.
+    fn stop(&self) {
+        self.stopped.store(true, SeqCst);
+        self.wake_all();
+    }
+
+    fn wake_all(&self) {
+        let _sleep = lock(&self.sleep);
+        self.wake.notify_all();
+    }
+}
+
+/// Records that a directory taken from the queue has been completely read once it is dropped,
+/// whether reading it finished or panicked. A panic stops the whole walk, as otherwise the other
+/// threads would wait forever for the directory, and the panic could never reach the caller.
+struct Done<'a>(&'a Queue);
+
+impl<'a> Drop for Done<'a> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.stop();
+        }
+        self.0.done();
+    }
+}
+
+/// Lock the given mutex. Nothing guarded by a lock in a walk can be left inconsistent by a panic,
+/// so a poisoned lock is ignored.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl<'a> WalkParallel<'a> {
+    /// Set the number of threads used to read directories. Defaults to the amount of parallelism
+    /// available on the machine; 0 is treated as 1.
+    pub fn threads(mut self, threads: usize) -> WalkParallel<'a> {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Whether to deliver entries sorted by path, so that the order is the same on every run.
+    /// Entries are then only delivered once the whole tree has been walked. Defaults to false, in
+    /// which case entries are delivered as soon as they are found, in no particular order.
+    pub fn sorted(mut self, sorted: bool) -> WalkParallel<'a> {
+        self.sorted = sorted;
+        self
+    }
+
+    /// Walk the tree, calling `f` with every entry that is not excluded. An error reading any
+    /// directory or entry is passed to `f` in its place, and the walk carries on with the rest of
+    /// the tree. `f` is always called on the current thread, so it does not need to be `Sync`.
+    pub fn run<F>(self, mut f: F) where F: FnMut(WalkResult) {
+        let WalkParallel { walker, root, threads, sorted } = self;
+        let path = root.path.clone();
+        let queue = Queue::new(root, threads);
+        let (walker, queue) = (&walker, &queue);
+
+        thread::scope(|scope| {
+            // The receiver belongs to this closure, so that if `f` panics it is dropped as the
+            // panic unwinds, before the threads are joined, and they stop rather than walking the
+            // rest of the tree for no one.
+            let (sender, receiver) = mpsc::channel();
+            for thread in 0..threads {
+                let sender = sender.clone();
+                scope.spawn(move || work(walker, queue, thread, sender));
+            }
+            drop(sender);
+
+            if sorted {
+                let mut results: Vec<_> = receiver.iter().collect();
+                results.sort_by(|a, b| compare(&path, a, b));
+                results.into_iter().for_each(&mut f);
+            } else {
+                receiver.iter().for_each(&mut f);
+            }
+        });
+    }
+}
+
+impl<'a> fmt::Debug for WalkParallel<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.debug_struct("WalkParallel")
+            .field("options", &self.walker.options)
+            .field("root", &self.root.path)
+            .field("threads", &self.threads)
+            .field("sorted", &self.sorted)
+            .finish()
+    }
+}
+
+/// Run one thread of a parallel walk: take directories from the queue until the whole tree has
+/// been read, sending what is found in each.
+fn work(walker: &Walker, queue: &Queue, thread: usize, sender: mpsc::Sender<WalkResult>) {
+    while let Some(dir) = queue.pop(thread) {
+        let _done = Done(queue);
+        let results = read_dir(walker, &dir, |found| queue.push(thread, found));
+
+        // The receiver only goes away if the caller panicked, so there is no one left to tell.
+        if results.into_iter().any(|result| sender.send(result).is_err()) {
+            queue.stop();
+        }
+    }
+}
+
+/// Read the entries of a directory, calling `found_dir` with every subdirectory that should be
+/// read in turn.
+fn read_dir<F>(walker: &Walker, dir: &Dir, mut found_dir: F) -> Vec<WalkResult> where F: FnMut(Dir) {
+    let entries = match fs::read_dir(&dir.path) {
+        Ok(entries) => entries,
+        Err(err) => return vec![Err(error::Error::io(err, &dir.path))]
+    };
+
+    entries.filter_map(|entry| {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => return Some(Err(error::Error::io(err, &dir.path)))
+        };
+
+        let (entry, found) = walker.visit(dir, path);
+        if let Some(found) = found {
+            found_dir(found);
+        }
+        entry
+    }).collect()
+}
+
+/// Order the results of a walk by path, placing an error for a directory before its contents.
+fn compare(root: &Path, a: &WalkResult, b: &WalkResult) -> Ordering {
+    let path = |result: &WalkResult| match *result {
+        Ok(ref entry) => entry.path.clone(),
+        Err(ref err) => err.path().unwrap_or(root).to_path_buf()
+    };
+    path(a).cmp(&path(b)).then_with(|| b.is_err().cmp(&a.is_err()))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::{Dir, WalkBuilder};
+
+    use error::ErrorKind;
+
+    use std::fs;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_walk_is_lazy_and_skips_excluded_directories() {
+        let dir = tempdir::TempDir::new("gitignore_tests").unwrap();
+        for path in &["a/b/c", "skip/d", ".git/objects"] {
+            fs::create_dir_all(dir.path().join(path)).unwrap();
+        }
+
+        let seen = Mutex::new(vec![]);
+        let walk = WalkBuilder::new(dir.path(), |path: &Path, _| {
+            seen.lock().unwrap().push(path.to_path_buf());
+            path.ends_with("skip")
+        }).build();
+        let entries: Vec<_> = walk.map(|entry| entry.unwrap()).collect();
+
+        let depths: Vec<(&Path, usize)> = entries.iter()
+            .map(|entry| (entry.path().strip_prefix(dir.path()).unwrap(), entry.depth()))
+            .collect();
+        assert!(depths.len() == 3);
+        assert!(depths.contains(&(Path::new("a"), 1)));
+        assert!(depths.contains(&(Path::new("a/b"), 2)));
+        assert!(depths.contains(&(Path::new("a/b/c"), 3)));
+        assert!(entries.iter().all(|entry| entry.is_dir()));
+        assert!(!seen.lock().unwrap().contains(&dir.path().join("skip/d")));
+
+        // nothing is read until it is asked for
+        let mut walk = WalkBuilder::new(dir.path(), |_: &Path, _| false).build();
+        assert!(walk.pending.len() == 1 && walk.current.is_none());
+        walk.next().unwrap().unwrap();
+        assert!(walk.current.is_some());
+    }
+
+    #[test]
+    fn test_walk_reports_errors_and_carries_on() {
+        let dir = tempdir::TempDir::new("gitignore_tests").unwrap();
+        let missing = dir.path().join("missing");
+
+        let mut walk = WalkBuilder::new(&missing, |_: &Path, _| false).build();
+        let err = walk.next().unwrap().unwrap_err();
+        assert!(err.kind() == ErrorKind::Io);
+        assert!(err.path() == Some(missing.as_path()));
+        assert!(walk.next().is_none());
+
+        fs::write(dir.path().join("file"), "").unwrap();
+        let mut walk = WalkBuilder::new(dir.path(), |_: &Path, _| false).build();
+        walk.pending.insert(0, Dir { path: missing.clone(), depth: 0, ancestors: None });
+        let results: Vec<_> = walk.collect();
+        assert!(results.len() == 2);
+        assert!(results.iter().any(|result| result.as_ref().is_ok_and(|entry| entry.file_name() == "file")));
+        assert!(results.iter().any(|result| result.is_err()));
+    }
+
+    #[test]
+    fn test_walk_parallel_matches_walk() {
+        let dir = tempdir::TempDir::new("gitignore_tests").unwrap();
+        for i in 0..5 {
+            for j in 0..5 {
+                let path = dir.path().join(format!("d{}/e{}", i, j));
+                fs::create_dir_all(&path).unwrap();
+                fs::write(path.join("file"), "").unwrap();
+                fs::write(path.join("skip"), "").unwrap();
+            }
+        }
+        fs::create_dir_all(dir.path().join(".git/objects")).unwrap();
+        let is_excluded = |path: &Path, _| path.ends_with("skip") || path.ends_with("d3");
+
+        let walk = WalkBuilder::new(dir.path(), is_excluded).build();
+        let mut expected: Vec<_> = walk.map(|entry| entry.unwrap().into_path()).collect();
+        expected.sort();
+        assert!(expected.len() == 4 + 4 * 5 * 2);
+
+        for &threads in &[1, 4] {
+            let mut found = vec![];
+            WalkBuilder::new(dir.path(), is_excluded).build_parallel().threads(threads).run(|entry| {
+                found.push(entry.unwrap().into_path());
+            });
+            found.sort();
+            assert!(found == expected);
+
+            let mut sorted = vec![];
+            let walk = WalkBuilder::new(dir.path(), is_excluded).build_parallel();
+            walk.threads(threads).sorted(true).run(|entry| {
+                sorted.push(entry.unwrap().into_path());
+            });
+            assert!(sorted == expected);
+        }
+    }
+
+    #[test]
+    fn test_walk_parallel_reports_errors() {
+        let dir = tempdir::TempDir::new("gitignore_tests").unwrap();
+        let missing = dir.path().join("missing");
+
+        let mut results = vec![];
+        WalkBuilder::new(&missing, |_: &Path, _| false).build_parallel().run(|result| results.push(result));
+        assert!(results.len() == 1);
+        assert!(results[0].as_ref().unwrap_err().path() == Some(missing.as_path()));
+    }
+
+    #[test]
+    fn test_walk_parallel_stops_when_callback_panics() {
+        let dir = tempdir::TempDir::new("gitignore_tests").unwrap();
+        for i in 0..100 {
+            for j in 0..10 {
+                fs::create_dir_all(dir.path().join(i.to_string()).join(j.to_string())).unwrap();
+            }
+        }
+
+        let visited = AtomicUsize::new(0);
+        let is_excluded = |_: &Path, _| {
+            visited.fetch_add(1, SeqCst);
+            thread::sleep(Duration::from_micros(200));
+            false
+        };
+        let walk = WalkBuilder::new(dir.path(), is_excluded).build_parallel().threads(2);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| walk.run(|_| panic!("callback failed"))));
+
+        assert!(result.is_err());
+        assert!(visited.load(SeqCst) < 1100 / 2);
+    }
+
+    #[test]
+    fn test_walk_parallel_propagates_panic_in_worker() {
+        let dir = tempdir::TempDir::new("gitignore_tests").unwrap();
+        for i in 0..20 {
+            for j in 0..10 {
+                fs::create_dir_all(dir.path().join(i.to_string()).join(j.to_string())).unwrap();
+            }
+        }
+        fs::create_dir_all(dir.path().join("5/boom")).unwrap();
+
+        let is_excluded = |path: &Path, _| {
+            assert!(!path.ends_with("boom"), "worker failed");
+            thread::sleep(Duration::from_micros(100));
+            false
+        };
+        let walk = WalkBuilder::new(dir.path(), is_excluded).build_parallel().threads(4);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| walk.run(|_| {})));
+
+        assert!(result.is_err());
+    }
+
+    fn walk_relative(builder: WalkBuilder, root: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = builder.build().map(|entry| {
+            entry.unwrap().path().strip_prefix(root).unwrap().to_path_buf()
+        }).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_walk_options() {
+        let dir = tempdir::TempDir::new("gitignore_tests").unwrap();
+        for path in &["a/b/c", ".hidden/d"] {
+            fs::create_dir_all(dir.path().join(path)).unwrap();
+        }
+        for path in &["a/one", "a/b/two", "a/b/c/three", ".hidden/four", ".dotfile"] {
+            fs::write(dir.path().join(path), "").unwrap();
+        }
+        let walk = |builder: WalkBuilder| walk_relative(builder, dir.path());
+        let builder = || WalkBuilder::new(dir.path(), |_: &Path, _| false);
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        assert!(walk(builder().max_depth(Some(1))) == paths(&[".dotfile", ".hidden", "a"]));
+        assert!(walk(builder().min_depth(3).max_depth(Some(3))) == paths(&["a/b/c", "a/b/two"]));
+        assert!(walk(builder().max_depth(Some(0))).is_empty());
+        assert!(walk(builder().skip_hidden(true).files(false)) == paths(&["a", "a/b", "a/b/c"]));
+        assert!(walk(builder().dirs(false).min_depth(3)) == paths(&["a/b/c/three", "a/b/two"]));
+        assert!(walk(builder().same_file_system(true)).len() == 10);
+
+        let skip = |path: &Path, _| path.ends_with("b") || path.ends_with("one") || path.ends_with(".hidden");
+        let ignored = || WalkBuilder::new(dir.path(), skip).ignored(true);
+        assert!(walk(ignored()) == paths(&[".hidden", "a/b", "a/one"]));
+        assert!(walk(ignored().dirs(false)) == paths(&["a/one"]));
+        assert!(walk(ignored().max_depth(Some(1))) == paths(&[".hidden"]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_skips_hidden_names_which_are_not_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempdir::TempDir::new("gitignore_tests").unwrap();
+        fs::write(dir.path().join(OsStr::from_bytes(b".caf\xe9")), "").unwrap();
+        fs::write(dir.path().join(OsStr::from_bytes(b"caf\xe9")), "").unwrap();
+
+        let builder = WalkBuilder::new(dir.path(), |_: &Path, _| false).skip_hidden(true);
+        let expected = vec![PathBuf::from(OsStr::from_bytes(b"caf\xe9"))];
+        assert!(walk_relative(builder, dir.path()) == expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_symlinks() {
+        use error::ErrorKind;
+        use std::os::unix::fs::symlink;
+
+        let dir = tempdir::TempDir::new("gitignore_tests").unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        fs::write(dir.path().join("a/b/file"), "").unwrap();
+        symlink(dir.path().join("a"), dir.path().join("a/b/loop")).unwrap();
+        symlink(dir.path().join("a/b"), dir.path().join("link")).unwrap();
+        symlink(dir.path().join("missing"), dir.path().join("broken")).unwrap();
+        let builder = || WalkBuilder::new(dir.path(), |_: &Path, _| false);
+        let relative = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        let mut loops = vec![];
+        let mut paths = vec![];
+        for entry in builder().follow_links(true).build() {
+            match entry {
+                Ok(entry) => paths.push(entry.path().strip_prefix(dir.path()).unwrap().to_path_buf()),
+                Err(err) => {
+                    assert!(err.kind() == ErrorKind::FilesystemLoop);
+                    loops.push(err.path().unwrap().strip_prefix(dir.path()).unwrap().to_path_buf());
+                }
+            }
+        }
+        paths.sort();
+        loops.sort();
+        assert!(paths == relative(&["a", "a/b", "a/b/file", "link", "link/file", "link/loop"]));
+        assert!(loops == relative(&["a/b/loop", "link/loop/b"]));
+
+        let unfollowed = walk_relative(builder(), dir.path());
+        assert!(unfollowed == relative(&["a", "a/b", "a/b/file", "a/b/loop", "broken", "link"]));
+        let links = builder().build().map(|entry| entry.unwrap().file_type());
+        assert!(links.filter(|file_type| file_type.is_symlink()).count() == 3);
+    }
+}
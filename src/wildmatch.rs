@@ -0,0 +1,637 @@
+use std::error;
+use std::fmt;
+
+/// Flags controlling how a glob is matched, mirroring those accepted by Git's `wildmatch`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Flags {
+    /// Whether ASCII letters match regardless of case (`WM_CASEFOLD`).
+    pub casefold: bool,
+    /// Whether wildcards are forbidden from matching a `/`, so that only `**` can cross
+    /// directories (`WM_PATHNAME`).
+    pub pathname: bool
+}
+
+/// A description of why a glob can never match anything, with the offset into the glob at which
+/// the problem was found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PatternError {
+    /// The offset into the glob at which the error was found.
+    pub pos: usize,
+    /// A message describing the error.
+    pub msg: &'static str
+}
+
+impl error::Error for PatternError {}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Pattern syntax error near position {}: {}", self.pos, self.msg)
+    }
+}
+
+/// The outcome of matching part of a glob, as in Git's `wildmatch.c`. The two abort results let a
+/// `*` give up early once it is known that consuming more text can never succeed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Match,
+    NoMatch,
+    AbortAll,
+    AbortToStarStar
+}
+
+/// Returns true if `text` is matched by the glob `pattern`. This is a faithful port of Git's
+/// `wildmatch`, supporting `?`, `*`, `**`, bracket expressions (including `[!...]`, `[^...]`,
+/// ranges and `[:class:]` names) and backslash escapes.
+pub(crate) fn wildmatch(pattern: &[u8], text: &[u8], flags: Flags) -> bool {
+    dowild(pattern, text, flags) == Outcome::Match
+}
+
+/// Check that the given glob is well formed, returning an error describing the first problem
+/// found if not. Malformed globs are not rejected by `wildmatch`, but they can never match
+/// anything, so they are almost certainly a mistake.
+pub(crate) fn validate(pattern: &[u8]) -> Result<(), PatternError> {
+    let mut p = 0;
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'\\' if p + 1 == pattern.len() => {
+                return Err(PatternError { pos: p, msg: "trailing backslash escapes nothing" });
+            },
+            b'\\' => p += 1,
+            b'[' => p = class_end(pattern, p)?,
+            _ => {}
+        }
+
+        p += 1;
+    }
+
+    Ok(())
+}
+
+/// Returns the byte at the given index, or `0` if the index is past the end, mirroring the NUL
+/// terminator the C implementation relies on.
+fn at(s: &[u8], i: usize) -> u8 {
+    s.get(i).cloned().unwrap_or(0)
+}
+
+fn fold(c: u8, flags: Flags) -> u8 {
+    if flags.casefold {
+        c.to_ascii_lowercase()
+    } else {
+        c
+    }
+}
+
+fn is_glob_special(c: u8) -> bool {
+    c == b'*' || c == b'?' || c == b'[' || c == b'\\'
+}
+
+fn dowild(p: &[u8], text: &[u8], flags: Flags) -> Outcome {
+    let mut pi = 0;
+    let mut ti = 0;
+
+    while pi < p.len() {
+        let mut t_ch = at(text, ti);
+        if t_ch == 0 && p[pi] != b'*' {
+            return Outcome::AbortAll;
+        }
+
+        t_ch = fold(t_ch, flags);
+        let p_ch = fold(p[pi], flags);
+
+        match p_ch {
+            b'\\' => {
+                // Literal match with the following character, which is not case folded. If there
+                // is no following character, this can never match.
+                pi += 1;
+                if t_ch != at(p, pi) {
+                    return Outcome::NoMatch;
+                }
+            },
+            b'?' => {
+                // Match anything but '/'.
+                if flags.pathname && t_ch == b'/' {
+                    return Outcome::NoMatch;
+                }
+            },
+            b'*' => {
+                let match_slash;
+                pi += 1;
+
+                if at(p, pi) == b'*' {
+                    let prev_is_slash = pi < 2 || p[pi - 2] == b'/';
+                    while at(p, pi) == b'*' {
+                        pi += 1;
+                    }
+
+                    let next = at(p, pi);
+                    let next_is_slash = next == b'/' || (next == b'\\' && at(p, pi + 1) == b'/');
+                    if prev_is_slash && (next == 0 || next_is_slash) {
+                        // Assuming we already match "foo/" and are at "**/", just assume it
+                        // matches nothing and go ahead matching the rest of the pattern with the
+                        // remaining text. This makes "foo/**/bar" match both "foo/bar" and
+                        // "foo/a/bar".
+                        if next == b'/' && dowild(&p[pi + 1..], &text[ti..], flags) == Outcome::Match {
+                            return Outcome::Match;
+                        }
+                        match_slash = true;
+                    } else {
+                        // Any other run of asterisks behaves just like a single one.
+                        match_slash = !flags.pathname;
+                    }
+                } else {
+                    // Without WM_PATHNAME, "*" is the same as "**".
+                    match_slash = !flags.pathname;
+                }
+
+                if pi == p.len() {
+                    // A trailing "**" matches everything. A trailing "*" matches only if there are
+                    // no more slash characters.
+                    if !match_slash && text[ti..].contains(&b'/') {
+                        return Outcome::NoMatch;
+                    }
+                    return Outcome::Match;
+                } else if !match_slash && p[pi] == b'/' {
+                    // A single asterisk followed by a slash matches up to the next directory.
+                    match text[ti..].iter().position(|&c| c == b'/') {
+                        Some(offset) => ti += offset,
+                        None => return Outcome::NoMatch
+                    }
+
+                    // The slash is consumed along with the one in the pattern.
+                    pi += 1;
+                    ti += 1;
+                    continue;
+                }
+
+                loop {
+                    if t_ch == 0 {
+                        break;
+                    }
+
+                    // Try to advance faster when an asterisk is followed by a literal, as the
+                    // text before the literal must belong to the asterisk. If the asterisk cannot
+                    // match a slash, do not look past the first one.
+                    if !is_glob_special(p[pi]) {
+                        let literal = fold(p[pi], flags);
+                        loop {
+                            t_ch = at(text, ti);
+                            if t_ch == 0 || (!match_slash && t_ch == b'/') {
+                                break;
+                            }
+
+                            t_ch = fold(t_ch, flags);
+                            if t_ch == literal {
+                                break;
+                            }
+                            ti += 1;
+                        }
+
+                        if t_ch != literal {
+                            return Outcome::NoMatch;
+                        }
+                    }
+
+                    let matched = dowild(&p[pi..], &text[ti..], flags);
+                    if matched != Outcome::NoMatch {
+                        if !match_slash || matched != Outcome::AbortToStarStar {
+                            return matched;
+                        }
+                    } else if !match_slash && t_ch == b'/' {
+                        return Outcome::AbortToStarStar;
+                    }
+
+                    ti += 1;
+                    t_ch = fold(at(text, ti), flags);
+                }
+
+                return Outcome::AbortAll;
+            },
+            b'[' => {
+                match match_class(p, &mut pi, t_ch, flags) {
+                    Some(true) if !(flags.pathname && t_ch == b'/') => {},
+                    Some(_) => return Outcome::NoMatch,
+                    None => return Outcome::AbortAll
+                }
+            },
+            _ => {
+                if t_ch != p_ch {
+                    return Outcome::NoMatch;
+                }
+            }
+        }
+
+        pi += 1;
+        ti += 1;
+    }
+
+    if ti < text.len() {
+        Outcome::NoMatch
+    } else {
+        Outcome::Match
+    }
+}
+
+/// Match the (already case folded) character `t_ch` against the bracket expression starting at
+/// `p[*pi]`, leaving `*pi` pointing at the closing bracket. Returns `None` if the expression is
+/// malformed and so can never match.
+fn match_class(p: &[u8], pi: &mut usize, t_ch: u8, flags: Flags) -> Option<bool> {
+    *pi += 1;
+    let mut p_ch = at(p, *pi);
+    if p_ch == b'^' {
+        p_ch = b'!';
+    }
+
+    let negated = p_ch == b'!';
+    if negated {
+        *pi += 1;
+        p_ch = at(p, *pi);
+    }
+
+    let mut prev_ch = 0;
+    let mut matched = false;
+
+    loop {
+        if p_ch == 0 {
+            return None;
+        }
+
+        if p_ch == b'\\' {
+            *pi += 1;
+            p_ch = at(p, *pi);
+            if p_ch == 0 {
+                return None;
+            }
+            if t_ch == p_ch {
+                matched = true;
+            }
+        } else if p_ch == b'-' && prev_ch != 0 && at(p, *pi + 1) != 0 && at(p, *pi + 1) != b']' {
+            *pi += 1;
+            p_ch = at(p, *pi);
+            if p_ch == b'\\' {
+                *pi += 1;
+                p_ch = at(p, *pi);
+                if p_ch == 0 {
+                    return None;
+                }
+            }
+
+            if t_ch <= p_ch && t_ch >= prev_ch {
+                matched = true;
+            } else if flags.casefold && t_ch.is_ascii_lowercase() {
+                let t_ch_upper = t_ch.to_ascii_uppercase();
+                if t_ch_upper <= p_ch && t_ch_upper >= prev_ch {
+                    matched = true;
+                }
+            }
+
+            // This makes the previous character unset, so a range cannot start here.
+            p_ch = 0;
+        } else if p_ch == b'[' && at(p, *pi + 1) == b':' {
+            let start = *pi + 2;
+            let mut end = start;
+            while at(p, end) != 0 && at(p, end) != b']' {
+                end += 1;
+            }
+
+            if at(p, end) == 0 {
+                return None;
+            }
+
+            if end == start || p[end - 1] != b':' {
+                // Didn't find ":]", so treat the "[" like a normal member of the set.
+                if t_ch == b'[' {
+                    matched = true;
+                }
+            } else {
+                *pi = end;
+                match char_class(&p[start..end - 1], t_ch, flags) {
+                    Some(true) => matched = true,
+                    Some(false) => {},
+                    None => return None
+                }
+
+                p_ch = 0;
+            }
+        } else if t_ch == p_ch {
+            matched = true;
+        }
+
+        prev_ch = p_ch;
+        *pi += 1;
+        p_ch = at(p, *pi);
+        if p_ch == b']' {
+            break;
+        }
+    }
+
+    Some(matched != negated)
+}
+
+/// Returns whether `t_ch` is a member of the named character class, eg. `alpha` for
+/// `[:alpha:]`, or `None` if there is no class with that name.
+fn char_class(name: &[u8], t_ch: u8, flags: Flags) -> Option<bool> {
+    let is_space = |c: u8| c == b' ' || c == b'\t' || c == b'\n' || c == b'\r';
+
+    Some(match name {
+        b"alnum" => t_ch.is_ascii_alphanumeric(),
+        b"alpha" => t_ch.is_ascii_alphabetic(),
+        b"blank" => t_ch == b' ' || t_ch == b'\t',
+        b"cntrl" => t_ch.is_ascii_control(),
+        b"digit" => t_ch.is_ascii_digit(),
+        b"graph" => t_ch.is_ascii_graphic(),
+        b"lower" => t_ch.is_ascii_lowercase(),
+        b"print" => t_ch.is_ascii_graphic() || t_ch == b' ',
+        b"punct" => t_ch.is_ascii_punctuation(),
+        b"space" => is_space(t_ch),
+        b"upper" => t_ch.is_ascii_uppercase() || (flags.casefold && t_ch.is_ascii_lowercase()),
+        b"xdigit" => t_ch.is_ascii_hexdigit(),
+        _ => return None
+    })
+}
+
+/// Returns the index of the bracket closing the bracket expression which starts at `p[start]`,
+/// or an error if it is never closed or names an unknown character class.
+fn class_end(p: &[u8], start: usize) -> Result<usize, PatternError> {
+    let unclosed = PatternError { pos: start, msg: "unclosed character class" };
+
+    let mut pi = start + 1;
+    if at(p, pi) == b'!' || at(p, pi) == b'^' {
+        pi += 1;
+    }
+
+    // The first member of the set may be a "]" without closing it.
+    loop {
+        match at(p, pi) {
+            0 => return Err(unclosed),
+            b'\\' => pi += 1,
+            b'[' if at(p, pi + 1) == b':' => {
+                let name_start = pi + 2;
+                let mut end = name_start;
+                while at(p, end) != 0 && at(p, end) != b']' {
+                    end += 1;
+                }
+
+                if at(p, end) == 0 {
+                    return Err(unclosed);
+                }
+
+                if end > name_start && p[end - 1] == b':' {
+                    if char_class(&p[name_start..end - 1], 0, Flags::default()).is_none() {
+                        return Err(PatternError { pos: pi, msg: "invalid character class name" });
+                    }
+                    pi = end;
+                }
+            },
+            _ => {}
+        }
+
+        if at(p, pi) == 0 {
+            return Err(unclosed);
+        }
+
+        pi += 1;
+        if at(p, pi) == b']' {
+            return Ok(pi);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Flags, PatternError, validate, wildmatch};
+
+    const PATHNAME: Flags = Flags { casefold: false, pathname: true };
+    const NONE: Flags = Flags { casefold: false, pathname: false };
+    const CASEFOLD: Flags = Flags { casefold: true, pathname: true };
+
+    /// Cases from Git's own `t3070-wildmatch.sh`, as (matches with `WM_PATHNAME`, matches
+    /// without it, text, pattern).
+    const CASES: &[(bool, bool, &str, &str)] = &[
+        // Basic wildmatch features
+        (true, true, "foo", "foo"),
+        (false, false, "foo", "bar"),
+        (true, true, "", ""),
+        (true, true, "foo", "???"),
+        (false, false, "foo", "??"),
+        (true, true, "foo", "*"),
+        (true, true, "foo", "f*"),
+        (false, false, "foo", "*f"),
+        (true, true, "foo", "*foo*"),
+        (true, true, "foobar", "*ob*a*r*"),
+        (true, true, "aaaaaaabababab", "*ab"),
+        (true, true, "foo*", "foo\\*"),
+        (false, false, "foobar", "foo\\*bar"),
+        (true, true, "f\\oo", "f\\\\oo"),
+        (true, true, "ball", "*[al]?"),
+        (false, false, "ten", "[ten]"),
+        (true, true, "ten", "**[!te]"),
+        (false, false, "ten", "**[!ten]"),
+        (true, true, "ten", "t[a-g]n"),
+        (false, false, "ten", "t[!a-g]n"),
+        (true, true, "ton", "t[!a-g]n"),
+        (true, true, "ton", "t[^a-g]n"),
+        (true, true, "a]b", "a[]]b"),
+        (true, true, "a-b", "a[]-]b"),
+        (true, true, "a]b", "a[]-]b"),
+        (false, false, "aab", "a[]-]b"),
+        (true, true, "aab", "a[]a-]b"),
+        (true, true, "]", "]"),
+        // Extended slash-matching features
+        (false, true, "foo/baz/bar", "foo*bar"),
+        (false, true, "foo/baz/bar", "foo**bar"),
+        (true, true, "foobazbar", "foo**bar"),
+        (true, true, "foo/baz/bar", "foo/**/bar"),
+        (true, true, "foo/baz/bar", "foo/**/**/bar"),
+        (true, true, "foo/b/a/z/bar", "foo/**/bar"),
+        (true, true, "foo/b/a/z/bar", "foo/**/**/bar"),
+        (true, true, "foo/bar", "foo/**/bar"),
+        (true, true, "foo/bar", "foo/**/**/bar"),
+        (false, true, "foo/bar", "foo?bar"),
+        (false, true, "foo/bar", "foo[/]bar"),
+        (false, true, "foo/bar", "foo[^a-z]bar"),
+        (false, true, "foo/bar", "f[^eiu][^eiu][^eiu][^eiu][^eiu]r"),
+        (true, true, "foo-bar", "f[^eiu][^eiu][^eiu][^eiu][^eiu]r"),
+        (true, true, "foo", "**/foo"),
+        (true, true, "XXX/foo", "**/foo"),
+        (true, true, "bar/baz/foo", "**/foo"),
+        (false, true, "bar/baz/foo", "*/foo"),
+        (false, true, "foo/bar/baz", "**/bar*"),
+        (true, true, "deep/foo/bar/baz", "**/bar/*"),
+        (false, true, "deep/foo/bar/baz/", "**/bar/*"),
+        (true, true, "deep/foo/bar/baz/", "**/bar/**"),
+        (false, false, "deep/foo/bar", "**/bar/*"),
+        (true, true, "deep/foo/bar/", "**/bar/**"),
+        (false, true, "foo/bar/baz", "**/bar**"),
+        (true, true, "foo/bar/baz/x", "*/bar/**"),
+        (false, true, "deep/foo/bar/baz/x", "*/bar/**"),
+        (true, true, "deep/foo/bar/baz/x", "**/bar/*/*"),
+        // Various additional tests
+        (false, false, "acrt", "a[c-c]st"),
+        (true, true, "acrt", "a[c-c]rt"),
+        (false, false, "]", "[!]-]"),
+        (true, true, "a", "[!]-]"),
+        (false, false, "", "\\"),
+        (false, false, "\\", "\\"),
+        (false, false, "XXX/\\", "*/\\"),
+        (true, true, "XXX/\\", "*/\\\\"),
+        (true, true, "@foo", "@foo"),
+        (false, false, "foo", "@foo"),
+        (true, true, "[ab]", "\\[ab]"),
+        (true, true, "[ab]", "[[]ab]"),
+        (true, true, "[ab]", "[[:]ab]"),
+        (false, false, "[ab]", "[[::]ab]"),
+        (true, true, "[ab]", "[[:digit]ab]"),
+        (true, true, "[ab]", "[\\[:]ab]"),
+        (true, true, "?a?b", "\\??\\?b"),
+        (true, true, "abc", "\\a\\b\\c"),
+        (false, false, "foo", ""),
+        (true, true, "foo/bar/baz/to", "**/t[o]"),
+        // Character class tests
+        (true, true, "a1B", "[[:alpha:]][[:digit:]][[:upper:]]"),
+        (false, false, "a", "[[:digit:][:upper:][:space:]]"),
+        (true, true, "A", "[[:digit:][:upper:][:space:]]"),
+        (true, true, "1", "[[:digit:][:upper:][:space:]]"),
+        (false, false, "1", "[[:digit:][:upper:][:spaci:]]"),
+        (true, true, " ", "[[:digit:][:upper:][:space:]]"),
+        (false, false, ".", "[[:digit:][:upper:][:space:]]"),
+        (true, true, ".", "[[:digit:][:punct:][:space:]]"),
+        (true, true, "5", "[[:xdigit:]]"),
+        (true, true, "f", "[[:xdigit:]]"),
+        (true, true, "D", "[[:xdigit:]]"),
+        (true, true, "_", "[[:alnum:][:alpha:][:blank:][:cntrl:][:digit:][:graph:][:lower:][:print:][:punct:][:space:][:upper:][:xdigit:]]"),
+        (true, true, ".", "[^[:alnum:][:alpha:][:blank:][:cntrl:][:digit:][:lower:][:space:][:upper:][:xdigit:]]"),
+        (true, true, "5", "[a-c[:digit:]x-z]"),
+        (true, true, "b", "[a-c[:digit:]x-z]"),
+        (true, true, "y", "[a-c[:digit:]x-z]"),
+        (false, false, "q", "[a-c[:digit:]x-z]"),
+        // Additional tests, including some malformed wildmatch patterns
+        (true, true, "]", "[\\\\-^]"),
+        (false, false, "[", "[\\\\-^]"),
+        (true, true, "-", "[\\-_]"),
+        (true, true, "]", "[\\]]"),
+        (false, false, "\\]", "[\\]]"),
+        (false, false, "\\", "[\\]]"),
+        (false, false, "ab", "a[]b"),
+        (false, false, "a[]b", "a[]b"),
+        (false, false, "ab[", "ab["),
+        (false, false, "ab", "[!"),
+        (false, false, "ab", "[-"),
+        (true, true, "-", "[-]"),
+        (false, false, "-", "[a-"),
+        (false, false, "-", "[!a-"),
+        (true, true, "-", "[--A]"),
+        (true, true, "5", "[--A]"),
+        (true, true, " ", "[ --]"),
+        (true, true, "$", "[ --]"),
+        (true, true, "-", "[ --]"),
+        (false, false, "0", "[ --]"),
+        (true, true, "-", "[---]"),
+        (true, true, "-", "[------]"),
+        (false, false, "j", "[a-e-n]"),
+        (true, true, "-", "[a-e-n]"),
+        (true, true, "a", "[!------]"),
+        (false, false, "[", "[]-a]"),
+        (true, true, "^", "[]-a]"),
+        (false, false, "^", "[!]-a]"),
+        (true, true, "[", "[!]-a]"),
+        (true, true, "^", "[a^bc]"),
+        (true, true, "-b]", "[a-]b]"),
+        (false, false, "\\", "[\\]"),
+        (true, true, "\\", "[\\\\]"),
+        (false, false, "\\", "[!\\\\]"),
+        (true, true, "G", "[A-\\\\]"),
+        (false, false, "aaabbb", "b*a"),
+        (false, false, "aabcaa", "*ba*"),
+        (true, true, ",", "[,]"),
+        (true, true, ",", "[\\\\,]"),
+        (true, true, "\\", "[\\\\,]"),
+        (true, true, "-", "[,-.]"),
+        (false, false, "+", "[,-.]"),
+        (false, false, "-.]", "[,-.]"),
+        (true, true, "2", "[\\1-\\3]"),
+        (true, true, "3", "[\\1-\\3]"),
+        (false, false, "4", "[\\1-\\3]"),
+        (true, true, "\\", "[[-\\]]"),
+        (true, true, "[", "[[-\\]]"),
+        (true, true, "]", "[[-\\]]"),
+        (false, false, "-", "[[-\\]]"),
+        // Test recursion
+        (true, true, "-adobe-courier-bold-o-normal--12-120-75-75-m-70-iso8859-1", "-*-*-*-*-*-*-12-*-*-*-m-*-*-*"),
+        (false, false, "-adobe-courier-bold-o-normal--12-120-75-75-X-70-iso8859-1", "-*-*-*-*-*-*-12-*-*-*-m-*-*-*"),
+        (false, false, "-adobe-courier-bold-o-normal--12-120-75-75-/-70-iso8859-1", "-*-*-*-*-*-*-12-*-*-*-m-*-*-*"),
+        (true, true, "XXX/adobe/courier/bold/o/normal//12/120/75/75/m/70/iso8859/1", "XXX/*/*/*/*/*/*/12/*/*/*/m/*/*/*"),
+        (false, false, "XXX/adobe/courier/bold/o/normal//12/120/75/75/X/70/iso8859/1", "XXX/*/*/*/*/*/*/12/*/*/*/m/*/*/*"),
+        (true, true, "abcd/abcdefg/abcdefghijk/abcdefghijklmnop.txt", "**/*a*b*g*n*t"),
+        (false, false, "abcd/abcdefg/abcdefghijk/abcdefghijklmnop.txtz", "**/*a*b*g*n*t"),
+        (false, false, "foo", "*/*/*"),
+        (false, false, "foo/bar", "*/*/*"),
+        (true, true, "foo/bba/arr", "*/*/*"),
+        (false, true, "foo/bb/aa/rr", "*/*/*"),
+        (true, true, "foo/bb/aa/rr", "**/**/**"),
+        (true, true, "abcXdefXghi", "*X*i"),
+        (false, true, "ab/cXd/efXg/hi", "*X*i"),
+        (true, true, "ab/cXd/efXg/hi", "*/*X*/*/*i"),
+        (true, true, "ab/cXd/efXg/hi", "**/*X*/**/*i")
+    ];
+
+    /// Cases from Git's own `t3070-wildmatch.sh` for case folding, as (matches case sensitively,
+    /// matches with `WM_CASEFOLD`, text, pattern).
+    const CASEFOLD_CASES: &[(bool, bool, &str, &str)] = &[
+        (false, true, "a", "[A-Z]"),
+        (true, true, "A", "[A-Z]"),
+        (false, true, "A", "[a-z]"),
+        (true, true, "a", "[a-z]"),
+        (false, true, "a", "[[:upper:]]"),
+        (true, true, "A", "[[:upper:]]"),
+        (false, true, "A", "[[:lower:]]"),
+        (true, true, "a", "[[:lower:]]"),
+        (false, true, "A", "[B-Za]"),
+        (true, true, "a", "[B-Za]"),
+        (false, true, "A", "[B-a]"),
+        (true, true, "a", "[B-a]"),
+        (false, true, "z", "[Z-y]"),
+        (true, true, "Z", "[Z-y]"),
+        (false, true, "Foo.O", "foo.o"),
+        (false, true, "a1B", "[[:alpha:]][[:digit:]][[:lower:]]")
+    ];
+
+    #[test]
+    fn test_wildmatch_cases() {
+        for &(pathname, none, text, pattern) in CASES {
+            assert!(wildmatch(pattern.as_bytes(), text.as_bytes(), PATHNAME) == pathname,
+                    "{:?} against {:?} with WM_PATHNAME", pattern, text);
+            assert!(wildmatch(pattern.as_bytes(), text.as_bytes(), NONE) == none,
+                    "{:?} against {:?}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn test_wildmatch_casefold_cases() {
+        for &(sensitive, casefold, text, pattern) in CASEFOLD_CASES {
+            assert!(wildmatch(pattern.as_bytes(), text.as_bytes(), PATHNAME) == sensitive,
+                    "{:?} against {:?}", pattern, text);
+            assert!(wildmatch(pattern.as_bytes(), text.as_bytes(), CASEFOLD) == casefold,
+                    "{:?} against {:?} with WM_CASEFOLD", pattern, text);
+        }
+    }
+
+    #[test]
+    fn test_wildmatch_non_utf8() {
+        assert!(wildmatch(b"*.txt", b"caf\xe9.txt", PATHNAME));
+        assert!(wildmatch(b"caf?.txt", b"caf\xe9.txt", PATHNAME));
+        assert!(!wildmatch(b"cafe.txt", b"caf\xe9.txt", CASEFOLD));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(b"foo").is_ok());
+        assert!(validate(b"f\\*o[]a-]b[!x][[:alpha:]]").is_ok());
+        assert!(validate(b"[[:]ab]").is_ok());
+        assert!(validate(b"foo\\") == Err(PatternError { pos: 3, msg: "trailing backslash escapes nothing" }));
+        assert!(validate(b"ab[") == Err(PatternError { pos: 2, msg: "unclosed character class" }));
+        assert!(validate(b"a[]b") == Err(PatternError { pos: 1, msg: "unclosed character class" }));
+        assert!(validate(b"[a-") == Err(PatternError { pos: 0, msg: "unclosed character class" }));
+        assert!(validate(b"x[[:spaci:]]") == Err(PatternError { pos: 2, msg: "invalid character class name" }));
+    }
+}